@@ -2,38 +2,155 @@
 in vec3 frag_normal;
 in vec3 frag_pos;
 in vec2 tex_coord;
+in vec3 frag_tint;
+in vec4 frag_light_space_pos;
 
 out vec4 color;
 
-uniform vec3 light_pos;
-uniform vec3 light_color;
-uniform mat4 view;
-uniform vec3 object_color;
+// Has to match MAX_LIGHTS in light.rs
+#define MAX_LIGHTS 16
+
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+struct Light {
+    int kind;
+    vec3 color;
+    vec3 position;
+    vec3 direction;
+    // Constant, linear and quadratic attenuation
+    vec3 attenuation;
+    // Cosines of the inner and outer angle of a spot light's cone
+    float inner_cutoff;
+    float outer_cutoff;
+};
+
+uniform Light lights[MAX_LIGHTS];
+uniform int light_count;
+uniform vec3 camera_position;
 uniform sampler2D texture_sampler;
+// Tangent space normals, flat for materials without a normal map
+uniform sampler2D normal_sampler;
+
+// Shadows of one directional light, `shadow_light` is -1 without shadows
+uniform int shadow_light;
+uniform sampler2DShadow shadow_map;
+uniform float shadow_bias;
+
+// Material values from the .mtl file, the specular color is black for
+// materials without highlights
+uniform vec3 material_ambient;
+uniform vec3 material_diffuse;
+uniform vec3 material_specular;
+uniform float material_shininess;
+uniform vec3 material_emissive;
+uniform float material_dissolve;
+
+// How much of the light reaches the fragment, averaged over a 3x3 block of
+// shadow map texels (percentage closer filtering)
+float shadow_factor(vec3 normal, vec3 light_dir) {
+    vec3 coords = frag_light_space_pos.xyz / frag_light_space_pos.w * 0.5 + 0.5;
+    if (any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+
+    // Surfaces at a grazing angle to the light need more bias against acne
+    float bias = shadow_bias * (1.0 + 9.0 * (1.0 - max(dot(normal, light_dir), 0.0)));
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(shadow_map, vec3(coords.xy + vec2(x, y) * texel, coords.z - bias));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Surface normal bent by the normal map. Meshes have no tangents, so the
+// tangent frame comes from how the position and texture coordinates change
+// across the triangle.
+vec3 mapped_normal(vec3 normal) {
+    vec3 dp1 = dFdx(frag_pos);
+    vec3 dp2 = dFdy(frag_pos);
+    vec2 duv1 = dFdx(tex_coord);
+    vec2 duv2 = dFdy(tex_coord);
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    // Texture coordinates run down the image, the green channel points up
+    vec3 bitangent = -(dp2perp * duv1.y + dp1perp * duv2.y);
+    float scale = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    // Without texture coordinates there is no frame to bend the normal in
+    if (scale <= 0.0) {
+        return normal;
+    }
+
+    vec3 mapped = texture(normal_sampler, tex_coord).xyz * 2.0 - 1.0;
+    mat3 frame = mat3(tangent * inversesqrt(scale), bitangent * inversesqrt(scale), normal);
+    return normalize(frame * mapped);
+}
 
 void main() {
     // Colors
-    vec3 object_color = texture(texture_sampler, tex_coord).rgb;;
-    vec3 diffuse_color = light_color;
-    vec3 specular_color = light_color;
-    vec3 ambient_color = vec3(1.0, 1.0, 1.0);
+    vec4 texel = texture(texture_sampler, tex_coord);
+    // Alpha tested textures cut out the transparent parts of a surface
+    if (texel.a < 0.5) {
+        discard;
+    }
+    // The mesh color tints the material, overlays have a white material and
+    // only show their tint
+    vec3 object_color = texel.rgb * material_diffuse * frag_tint;
+    vec3 ambient_color = material_ambient * object_color;
 
     // Parameters
     float ambient_brightness = 0.1;
 
-
     // Parameters needed for light calculation
-    vec3 view_pos = -vec3(view[3][0], view[3][1], view[3][2]);
-    vec3 light_dir = normalize(light_pos - frag_pos);
-    vec3 view_dir = normalize(view_pos - frag_pos);
-    vec3 reflect_dir = reflect(-light_dir, normalize(frag_normal));
+    vec3 view_dir = normalize(camera_position - frag_pos);
+    vec3 normal = mapped_normal(normalize(frag_normal));
+
+    // Light calculations, summed over all lights
+    vec3 diffuse = vec3(0.0);
+    vec3 specular = vec3(0.0);
+    for (int i = 0; i < light_count; i++) {
+        Light light = lights[i];
+
+        vec3 light_dir;
+        float strength = 1.0;
+        if (light.kind == DIRECTIONAL_LIGHT) {
+            light_dir = -light.direction;
+        } else {
+            vec3 to_light = light.position - frag_pos;
+            float distance = length(to_light);
+            light_dir = to_light / distance;
+            strength = 1.0 / (light.attenuation.x + light.attenuation.y * distance
+                + light.attenuation.z * distance * distance);
+
+            if (light.kind == SPOT_LIGHT) {
+                float theta = dot(light_dir, -light.direction);
+                strength *= clamp((theta - light.outer_cutoff)
+                    / (light.inner_cutoff - light.outer_cutoff), 0.0, 1.0);
+            }
+        }
 
-    // Light calculations
-    float diffuse = clamp(dot(normalize(frag_normal), light_dir), 0, 1);
+        if (i == shadow_light) {
+            strength *= shadow_factor(normal, light_dir);
+        }
 
-    vec3 specular = pow(max(dot(view_dir, reflect_dir), 0.0), 32) * specular_color;
+        float lambert = dot(normal, light_dir);
+        diffuse += clamp(lambert, 0, 1) * strength * light.color;
 
+        // Blinn-Phong highlight, only on the side facing the light
+        if (lambert > 0.0) {
+            vec3 halfway_dir = normalize(light_dir + view_dir);
+            specular += pow(max(dot(normal, halfway_dir), 0.0), material_shininess)
+                * strength * light.color;
+        }
+    }
 
-    vec3 result = (diffuse +/* specular +*/ ambient_color * ambient_brightness) * object_color;
-    color = vec4(result, 1.0);
+    // Without lights only the ambient light is left
+    vec3 result = diffuse * object_color + specular * material_specular
+        + ambient_color * ambient_brightness + material_emissive;
+    color = vec4(result, texel.a * material_dissolve);
 }
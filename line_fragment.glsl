@@ -0,0 +1,9 @@
+#version 330 core
+in vec3 frag_color;
+
+out vec4 color;
+
+// Lines are not lit, they always show their own color
+void main() {
+    color = vec4(frag_color, 1.0);
+}
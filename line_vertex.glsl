@@ -0,0 +1,13 @@
+#version 330 core
+in vec3 position;
+in vec3 color;
+
+out vec3 frag_color;
+
+uniform mat4 view;
+uniform mat4 projection;
+
+void main() {
+    gl_Position = projection * view * vec4(position, 1.0);
+    frag_color = color;
+}
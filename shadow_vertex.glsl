@@ -0,0 +1,11 @@
+#version 330 core
+in vec3 position;
+
+// Per-instance attributes
+in mat4 model;
+
+uniform mat4 light_space;
+
+void main() {
+    gl_Position = light_space * model * vec4(position, 1.0);
+}
@@ -0,0 +1,512 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glium::{backend::Facade, Program};
+use nalgebra_glm::Vec3;
+
+use crate::{
+    material::TextureSource,
+    mesh::{MaterialTextures, Mesh},
+    mesh_data::{MeshData, MeshError},
+    shader::{self, ShaderError},
+    texture::{ColorSpace, Texture, TextureError, TextureOptions},
+};
+
+// Reference to an asset in an `AssetStore`. Handles are cheap to clone and can
+// be stored as components, an asset is kept loaded as long as a handle to it exists.
+pub struct Handle<T> {
+    id: Arc<u32>,
+    asset: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    // Handle that refers to no store, for tests of systems that only pass
+    // handles on
+    #[cfg(test)]
+    pub fn detached(id: u32) -> Self {
+        Handle {
+            id: Arc::new(id),
+            asset: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            id: self.id.clone(),
+            asset: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.id)
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+pub type MeshId = Handle<Mesh>;
+pub type TextureId = Handle<Texture>;
+pub type ShaderId = Handle<Program>;
+
+struct Entry<T, K> {
+    asset: T,
+    key: Option<K>,
+    // The store's own handle, the asset is unused when it is the only one left
+    handle: Handle<T>,
+}
+
+// Assets of one kind, optionally keyed by the files they were loaded from so
+// every file is only loaded once
+pub struct AssetStore<T, K = PathBuf> {
+    entries: HashMap<u32, Entry<T, K>>,
+    keys: HashMap<K, u32>,
+    next_id: u32,
+}
+
+impl<T, K> Default for AssetStore<T, K> {
+    fn default() -> Self {
+        AssetStore {
+            entries: HashMap::new(),
+            keys: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<T, K: Eq + Hash + Clone> AssetStore<T, K> {
+    // Add an asset that wasn't loaded from a file
+    pub fn insert(&mut self, asset: T) -> Handle<T> {
+        let handle = Handle {
+            id: Arc::new(self.next_id),
+            asset: PhantomData,
+        };
+        self.entries.insert(
+            self.next_id,
+            Entry {
+                asset,
+                key: None,
+                handle: handle.clone(),
+            },
+        );
+        self.next_id += 1;
+        handle
+    }
+
+    // Add an asset loaded from `key`, replacing what was loaded from it before
+    pub fn insert_keyed(&mut self, key: K, asset: T) -> Handle<T> {
+        if let Some(handle) = self.get_by_key(&key) {
+            self.entries.get_mut(&handle.id).unwrap().asset = asset;
+            return handle;
+        }
+
+        let handle = self.insert(asset);
+        self.entries.get_mut(&handle.id).unwrap().key = Some(key.clone());
+        self.keys.insert(key, *handle.id);
+        handle
+    }
+
+    pub fn get(&self, handle: &Handle<T>) -> &T {
+        &self
+            .entries
+            .get(&handle.id)
+            .expect("Handle does not correspond to any asset in store")
+            .asset
+    }
+
+    pub fn get_mut(&mut self, handle: &Handle<T>) -> &mut T {
+        &mut self
+            .entries
+            .get_mut(&handle.id)
+            .expect("Handle does not correspond to any asset in store")
+            .asset
+    }
+
+    // Key the asset was loaded from, if any
+    pub fn key(&self, handle: &Handle<T>) -> Option<&K> {
+        self.entries.get(&handle.id)?.key.as_ref()
+    }
+
+    pub fn get_by_key(&self, key: &K) -> Option<Handle<T>> {
+        let id = self.keys.get(key)?;
+        Some(self.entries[id].handle.clone())
+    }
+
+    // Assets loaded from a file, with the key they were loaded from
+    pub fn keyed(&self) -> impl Iterator<Item = (K, Handle<T>)> + '_ {
+        self.entries
+            .values()
+            .filter_map(|entry| Some((entry.key.clone()?, entry.handle.clone())))
+    }
+
+    // Drop every asset no handle refers to anymore, returns how many were dropped
+    pub fn unload_unused(&mut self) -> usize {
+        let unused: Vec<u32> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| Arc::strong_count(&entry.handle.id) == 1)
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in &unused {
+            if let Some(key) = self.entries.remove(id).and_then(|entry| entry.key) {
+                self.keys.remove(&key);
+            }
+        }
+        unused.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T> AssetStore<T, PathBuf> {
+    // Look up an asset by the path it was loaded from. A bare file name like
+    // "tank.obj" also finds "assets/tank.obj", as long as only one file matches.
+    pub fn find(&self, name: &str) -> Option<Handle<T>> {
+        let name = Path::new(name);
+        if let Some(handle) = self.get_by_key(&name.to_path_buf()) {
+            return Some(handle);
+        }
+
+        let mut matches = self.keys.iter().filter(|(path, _)| path.ends_with(name));
+        match (matches.next(), matches.next()) {
+            (Some((_, id)), None) => Some(self.entries[id].handle.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Every mesh, texture and shader program used by the game
+pub struct Assets {
+    pub meshes: AssetStore<Mesh>,
+    pub textures: AssetStore<Texture>,
+    // Programs are keyed by their vertex and fragment shader files
+    pub shaders: AssetStore<Program, (PathBuf, PathBuf)>,
+    // Default textures for maps a material doesn't have. They are created
+    // once and never unloaded, as these handles keep them in use.
+    white_texture: TextureId,
+    normal_texture: TextureId,
+}
+
+impl Assets {
+    pub fn new(display: &impl Facade) -> Self {
+        let mut textures = AssetStore::default();
+        let white_texture = textures.insert(Texture::solid(
+            display,
+            [255, 255, 255, 255],
+            ColorSpace::Srgb,
+        ));
+        // Normal pointing straight out of the surface
+        let normal_texture = textures.insert(Texture::solid(
+            display,
+            [128, 128, 255, 255],
+            ColorSpace::Linear,
+        ));
+        Assets {
+            meshes: AssetStore::default(),
+            textures,
+            shaders: AssetStore::default(),
+            white_texture,
+            normal_texture,
+        }
+    }
+
+    // Load a mesh file, or get the mesh if the file was loaded before
+    pub fn load_mesh(
+        &mut self,
+        display: &impl Facade,
+        path: impl Into<PathBuf>,
+        color: Vec3,
+    ) -> Result<MeshId, MeshError> {
+        let path = path.into();
+        if let Some(handle) = self.meshes.get_by_key(&path) {
+            return Ok(handle);
+        }
+
+        let data = MeshData::load(path.clone())?;
+        Ok(self.insert_mesh(display, Some(path), data, color))
+    }
+
+    // Upload mesh data, loading the textures of its materials. Mesh data that was
+    // loaded from `path` can be found by it later.
+    pub fn insert_mesh(
+        &mut self,
+        display: &impl Facade,
+        path: Option<PathBuf>,
+        data: MeshData,
+        color: Vec3,
+    ) -> MeshId {
+        let mesh = self.upload_mesh(display, data, color);
+        match path {
+            Some(path) => self.meshes.insert_keyed(path, mesh),
+            None => self.meshes.insert(mesh),
+        }
+    }
+
+    fn upload_mesh(&mut self, display: &impl Facade, data: MeshData, color: Vec3) -> Mesh {
+        let linear = TextureOptions {
+            color_space: ColorSpace::Linear,
+            ..Default::default()
+        };
+        let textures = data
+            .materials
+            .iter()
+            .map(|material| MaterialTextures {
+                diffuse: self.load_map(
+                    display,
+                    &material.diffuse_map,
+                    TextureOptions::default(),
+                    self.white_texture.clone(),
+                ),
+                normal: self.load_map(
+                    display,
+                    &material.bump_map,
+                    linear,
+                    self.normal_texture.clone(),
+                ),
+            })
+            .collect();
+
+        Mesh::upload(display, data, color, textures, self.default_textures())
+    }
+
+    // Texture of a material map, or the default when there is none or it fails
+    // to load
+    fn load_map(
+        &mut self,
+        display: &impl Facade,
+        map: &Option<TextureSource>,
+        options: TextureOptions,
+        default: TextureId,
+    ) -> TextureId {
+        match map {
+            Some(source) => self
+                .load_texture_with(display, source, options)
+                .unwrap_or_else(|err| {
+                    eprintln!("Failed to load texture {:?}: {}", source, err);
+                    default
+                }),
+            None => default,
+        }
+    }
+
+    // Textures of faces without a material
+    pub fn default_textures(&self) -> MaterialTextures {
+        MaterialTextures {
+            diffuse: self.white_texture.clone(),
+            normal: self.normal_texture.clone(),
+        }
+    }
+
+    // Load a texture file once, embedded images are uploaded every time
+    pub fn load_texture(
+        &mut self,
+        display: &impl Facade,
+        source: &TextureSource,
+    ) -> Result<TextureId, TextureError> {
+        self.load_texture_with(display, source, TextureOptions::default())
+    }
+
+    // Load a texture with the given sampler options. A file that was loaded
+    // before is returned as is, with the options it was first loaded with.
+    pub fn load_texture_with(
+        &mut self,
+        display: &impl Facade,
+        source: &TextureSource,
+        options: TextureOptions,
+    ) -> Result<TextureId, TextureError> {
+        if let TextureSource::File(path) = source {
+            if let Some(handle) = self.textures.get_by_key(path) {
+                return Ok(handle);
+            }
+        }
+
+        let texture = Texture::load(display, source, options)?;
+        Ok(match source {
+            TextureSource::File(path) => self.textures.insert_keyed(path.clone(), texture),
+            TextureSource::Image(_) => self.textures.insert(texture),
+        })
+    }
+
+    pub fn load_shader(
+        &mut self,
+        display: &impl Facade,
+        vertex: impl Into<PathBuf>,
+        fragment: impl Into<PathBuf>,
+    ) -> Result<ShaderId, ShaderError> {
+        let key = (vertex.into(), fragment.into());
+        if let Some(handle) = self.shaders.get_by_key(&key) {
+            return Ok(handle);
+        }
+
+        let program = shader::load(display, key.1.clone(), key.0.clone())?;
+        Ok(self.shaders.insert_keyed(key, program))
+    }
+
+    // Load the files of a mesh, texture or shader again after they changed on
+    // disk. Handles stay valid, and on failure the old asset is kept.
+    pub fn reload_mesh(&mut self, display: &impl Facade, handle: &MeshId) -> Result<(), MeshError> {
+        let Some(path) = self.meshes.key(handle).cloned() else {
+            return Ok(());
+        };
+        let data = MeshData::load(path)?;
+        let mesh = self.upload_mesh(display, data, self.mesh(handle).color);
+        *self.meshes.get_mut(handle) = mesh;
+        Ok(())
+    }
+
+    pub fn reload_texture(
+        &mut self,
+        display: &impl Facade,
+        handle: &TextureId,
+    ) -> Result<(), TextureError> {
+        let Some(path) = self.textures.key(handle).cloned() else {
+            return Ok(());
+        };
+        let options = self.texture(handle).options;
+        *self.textures.get_mut(handle) =
+            Texture::load(display, &TextureSource::File(path), options)?;
+        Ok(())
+    }
+
+    pub fn reload_shader(
+        &mut self,
+        display: &impl Facade,
+        handle: &ShaderId,
+    ) -> Result<(), ShaderError> {
+        let Some((vertex, fragment)) = self.shaders.key(handle).cloned() else {
+            return Ok(());
+        };
+        *self.shaders.get_mut(handle) = shader::load(display, fragment, vertex)?;
+        Ok(())
+    }
+
+    pub fn mesh(&self, handle: &MeshId) -> &Mesh {
+        self.meshes.get(handle)
+    }
+
+    pub fn texture(&self, handle: &TextureId) -> &Texture {
+        self.textures.get(handle)
+    }
+
+    pub fn shader(&self, handle: &ShaderId) -> &Program {
+        self.shaders.get(handle)
+    }
+
+    // Texture of materials without a diffuse map and of overlays
+    pub fn white_texture(&self) -> &Texture {
+        self.textures.get(&self.white_texture)
+    }
+
+    // Flat normal map of materials without one and of overlays
+    pub fn normal_texture(&self) -> &Texture {
+        self.textures.get(&self.normal_texture)
+    }
+
+    // Drop assets that nothing refers to anymore. Meshes go first, so the
+    // textures only they were using are dropped along with them. Nothing is
+    // unloaded on its own, the game calls this when asked to (F8).
+    pub fn unload_unused(&mut self) -> usize {
+        self.meshes.unload_unused() + self.textures.unload_unused() + self.shaders.unload_unused()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_assets_are_stored_once() {
+        let mut store: AssetStore<&str> = AssetStore::default();
+        let first = store.insert_keyed("tank.obj".into(), "old");
+        assert_eq!(store.get_by_key(&"tank.obj".into()), Some(first.clone()));
+
+        // Loading the same file again replaces the asset behind the same handle
+        let second = store.insert_keyed("tank.obj".into(), "new");
+        assert_eq!(first, second);
+        assert_eq!(store.len(), 1);
+        assert_eq!(*store.get(&first), "new");
+        assert_eq!(store.key(&first), Some(&PathBuf::from("tank.obj")));
+
+        let unkeyed = store.insert("generated");
+        assert_ne!(unkeyed, first);
+        assert_eq!(store.key(&unkeyed), None);
+        assert_eq!(store.get_by_key(&"generated".into()), None);
+    }
+
+    #[test]
+    fn unused_assets_are_dropped() {
+        let mut store: AssetStore<&str> = AssetStore::default();
+        let kept = store.insert_keyed("kept.png".into(), "kept");
+        let copy = kept.clone();
+        drop(kept);
+        store.insert_keyed("dropped.png".into(), "dropped");
+        store.insert("dropped too");
+
+        assert_eq!(store.unload_unused(), 2);
+        assert_eq!(store.len(), 1);
+        assert_eq!(*store.get(&copy), "kept");
+        assert_eq!(store.get_by_key(&"dropped.png".into()), None);
+        assert_eq!(store.unload_unused(), 0);
+
+        drop(copy);
+        assert_eq!(store.unload_unused(), 1);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn find_matches_the_end_of_a_path() {
+        let mut store: AssetStore<&str> = AssetStore::default();
+        let tank = store.insert_keyed("models/tank.obj".into(), "tank");
+        let red = store.insert_keyed("red/tank.png".into(), "red");
+        store.insert_keyed("blue/tank.png".into(), "blue");
+
+        assert_eq!(store.find("models/tank.obj"), Some(tank.clone()));
+        assert_eq!(store.find("tank.obj"), Some(tank));
+        // Only whole file names match
+        assert_eq!(store.find("ank.obj"), None);
+        // Two files are called "tank.png"
+        assert_eq!(store.find("tank.png"), None);
+        assert_eq!(store.find("red/tank.png"), Some(red));
+    }
+
+    #[test]
+    fn default_textures_stay_loaded() {
+        let context = crate::headless::create_context().unwrap();
+        let mut assets = Assets::new(&context);
+        assert_eq!(assets.unload_unused(), 0);
+        assert_eq!(assets.textures.len(), 2);
+        assert_eq!(assets.white_texture().options.color_space, ColorSpace::Srgb);
+        assert_eq!(
+            assets.normal_texture().options.color_space,
+            ColorSpace::Linear
+        );
+    }
+}
@@ -2,7 +2,7 @@ use std::f32::consts::PI;
 
 use nalgebra_glm::Vec2;
 
-use crate::{vertex::Vertex, mesh::Mesh};
+use crate::{vertex::Vertex, mesh_data::MeshData};
 
 #[derive(Copy, Clone, Debug)]
 pub struct BoundingCircle {
@@ -11,18 +11,18 @@ pub struct BoundingCircle {
 }
 
 impl BoundingCircle {
-    pub fn from_mesh(mesh: &Mesh) -> Self {
+    pub fn from_mesh(mesh: &MeshData) -> Self {
         let projected: Vec<Vec2> = mesh
             .vertices
             .iter()
             .map(|v| Vec2::new(v.position[0], v.position[2]))
             .collect();
 
-        let midpoint = projected
-            .clone()
-            .into_iter()
-            .reduce(|a: Vec2, b: Vec2| (a + b) / (projected.len() as f32))
-            .unwrap();
+        // Center of the mesh's bounding box seen from above. Unlike an average
+        // of the vertices it doesn't lean towards detailed parts of the mesh,
+        // which would make the circle larger than it needs to be.
+        let center = mesh.bounds.center();
+        let midpoint = Vec2::new(center.x, center.z);
 
         let mut max_r = 0.0;
         for v in projected {
@@ -70,3 +70,61 @@ impl BoundingCircle {
         vertices
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_data::Bounds;
+
+    fn circle_of(path: &str) -> BoundingCircle {
+        BoundingCircle::from_mesh(&MeshData::load_source(path.into()).unwrap())
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn encloses_the_mesh_seen_from_above() {
+        let circle = circle_of("cube.obj");
+        assert_eq!(circle.ground_pos, Vec2::zeros());
+        // Corners of the 2x2 square are sqrt(2) from the center, plus a margin
+        assert_close(circle.r, 2.0f32.sqrt() * 1.2);
+    }
+
+    #[test]
+    fn centered_on_the_bounds() {
+        // Quad standing upright from (0, 0, 0) to (1, 1, 0)
+        let circle = circle_of("triangle.obj");
+        assert_eq!(circle.ground_pos, Vec2::new(0.5, 0.0));
+        assert_close(circle.r, 0.5 * 1.2);
+    }
+
+    #[test]
+    fn detail_does_not_move_the_center() {
+        // Most vertices are at the right end of a 4 units long strip, their
+        // average would be far right of the middle
+        let vertices: Vec<Vertex> = [0.0, 4.0, 4.0, 4.0, 4.0, 4.0]
+            .iter()
+            .zip([0.0, 0.0, 1.0, 0.5, -0.5, -1.0])
+            .map(|(&x, z)| Vertex {
+                position: [x, 0.0, z],
+                normal: [0.0, 1.0, 0.0],
+                texture_coord: [0.0, 0.0],
+            })
+            .collect();
+        let mesh = MeshData {
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices: vec![0, 1, 2, 0, 3, 4, 0, 5, 1],
+            materials: vec![],
+            parts: vec![],
+            dependencies: vec![],
+        };
+
+        let circle = BoundingCircle::from_mesh(&mesh);
+        assert_eq!(circle.ground_pos, Vec2::new(2.0, 0.0));
+        // The corners at (4, 1) and (4, -1) are furthest from the middle
+        assert_close(circle.r, 5.0f32.sqrt() * 1.2);
+    }
+}
@@ -1,61 +1,490 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    f32::consts::{PI, TAU},
+};
 
-use glium::glutin::event::VirtualKeyCode;
-use hecs::{World, Entity};
-use nalgebra_glm::{look_at, vec3, Mat4, Vec3};
+use glium::glutin::event::{ModifiersState, VirtualKeyCode};
+use hecs::{Entity, World};
+use nalgebra_glm::{look_at, vec2, vec3, Mat4, Vec2, Vec3};
+
+use crate::{mouse::Mouse, transformation::Transformation};
+
+// Vertical field of view
+const FOV: f32 = std::f32::consts::FRAC_PI_4;
+
+// Angle between the ground and the line from the focus point to the camera
+const PITCH: f32 = 1.13;
+// Panning speed in focus distances per second, so panning feels the same at
+// every zoom level
+const PAN_SPEED: f32 = 1.0;
+// Yaw rotation speed of Q and E in radians per second
+const ROTATE_SPEED: f32 = 2.0;
+// Yaw rotation of dragging with the middle mouse button in radians per pixel
+const DRAG_ROTATE_SPEED: f32 = 0.01;
+// Factor the distance is multiplied by for every line scrolled
+const ZOOM_FACTOR: f32 = 0.9;
+// How quickly the focus point, yaw and distance ease towards their targets,
+// higher is faster
+const EASE_SHARPNESS: f32 = 8.0;
+// Pixels from the edge of the window where the cursor scrolls the camera
+const EDGE_MARGIN: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionKind {
+    Perspective,
+    // Parallel projection without foreshortening, every unit looks the same
+    // size wherever it is on screen
+    Orthographic,
+}
 
 pub struct Camera {
     pub view: Mat4,
     pub projection: Mat4,
     pub position: Vec3,
+    kind: ProjectionKind,
+    near: f32,
+    far: f32,
+    aspect_ratio: f32,
+    // Half the height of the area an orthographic projection shows
+    ortho_extent: f32,
 }
 
-fn view_from_pos(pos: Vec3) -> Mat4 {
-    look_at(
-        &pos,
-        &(vec3(pos.x - 10.0, 0.0, pos.z - 10.0)),
-        &vec3(0.0, 1.0, 0.0),
-    )
+impl Camera {
+    // Perspective camera looking at a viewport of `width` by `height` pixels.
+    // It looks down -z from the origin until it is placed with `look_at`.
+    pub fn new(near: f32, far: f32, width: u32, height: u32) -> Self {
+        let mut camera = Camera {
+            view: Mat4::identity(),
+            projection: Mat4::identity(),
+            position: Vec3::zeros(),
+            kind: ProjectionKind::Perspective,
+            near,
+            far,
+            aspect_ratio: 1.0,
+            ortho_extent: 10.0,
+        };
+        camera.resize(width, height);
+        camera
+    }
+
+    // Match the projection to the aspect ratio of a new viewport size.
+    // Minimized windows have a size of zero, the projection is kept then.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.aspect_ratio = width as f32 / height as f32;
+        self.update_projection();
+    }
+
+    pub fn kind(&self) -> ProjectionKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: ProjectionKind) {
+        self.kind = kind;
+        self.update_projection();
+    }
+
+    pub fn toggle_kind(&mut self) {
+        self.set_kind(match self.kind {
+            ProjectionKind::Perspective => ProjectionKind::Orthographic,
+            ProjectionKind::Orthographic => ProjectionKind::Perspective,
+        });
+    }
+
+    // Show `extent` units above and below the middle of the screen when orthographic
+    pub fn set_ortho_extent(&mut self, extent: f32) {
+        self.ortho_extent = extent;
+        self.update_projection();
+    }
+
+    fn update_projection(&mut self) {
+        self.projection = match self.kind {
+            ProjectionKind::Perspective => {
+                Mat4::new_perspective(self.aspect_ratio, FOV, self.near, self.far)
+            }
+            ProjectionKind::Orthographic => {
+                let height = self.ortho_extent;
+                let width = height * self.aspect_ratio;
+                Mat4::new_orthographic(-width, width, -height, height, self.near, self.far)
+            }
+        };
+    }
+
+    pub fn look_at(&mut self, eye: Vec3, target: Vec3) {
+        self.position = eye;
+        self.view = look_at(&eye, &target, &Vec3::y());
+    }
 }
 
-impl Camera {
-    pub fn new(pos: Vec3, near: f32, far: f32) -> Self {
-        Camera {
-            view: view_from_pos(pos),
-            projection: Mat4::new_perspective(
-                800.0 / 600.0,
-                nalgebra_glm::pi::<f32>() / 4.0,
-                near,
-                far,
-            ),
-            position: pos,
+// Number of camera bookmarks, stored and recalled with F1 to F4
+pub const BOOKMARKS: usize = 4;
+
+// Where a camera rig is headed, kept by bookmarks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraView {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub distance: f32,
+}
+
+// Places the camera of its entity, looking down at a focus point on the
+// ground from a fixed pitch. The camera can be rotated around the focus
+// point, zoomed towards it and panned over the map.
+//
+// Input only changes the targets, the focus point, yaw and distance ease
+// towards them every frame, so every move of the camera is smooth.
+pub struct CameraRig {
+    pub focus: Vec3,
+    pub target_focus: Vec3,
+    // Rotation around the vertical axis, the camera is towards +z of the
+    // focus point at 0
+    pub yaw: f32,
+    pub target_yaw: f32,
+    // Distance of the camera from the focus point
+    pub distance: f32,
+    pub target_distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    // Corners of the area of the ground the focus point stays within
+    pub bounds_min: Vec2,
+    pub bounds_max: Vec2,
+    // Entity whose `Transformation` the focus point follows
+    pub follow: Option<Entity>,
+    pub bookmarks: [Option<CameraView>; BOOKMARKS],
+}
+
+impl CameraRig {
+    pub fn new(focus: Vec3, yaw: f32, distance: f32, bounds_min: Vec2, bounds_max: Vec2) -> Self {
+        CameraRig {
+            focus,
+            target_focus: focus,
+            yaw,
+            target_yaw: yaw,
+            distance,
+            target_distance: distance,
+            min_distance: 5.0,
+            max_distance: 40.0,
+            bounds_min,
+            bounds_max,
+            follow: None,
+            bookmarks: [None; BOOKMARKS],
         }
     }
-    pub fn update_view(&mut self) {
-        self.view = view_from_pos(self.position);
+
+    pub fn eye(&self) -> Vec3 {
+        let offset = vec3(
+            self.yaw.sin() * PITCH.cos(),
+            PITCH.sin(),
+            self.yaw.cos() * PITCH.cos(),
+        );
+        self.focus + offset * self.distance
+    }
+
+    // Direction on the ground the camera looks in
+    pub fn forward(&self) -> Vec3 {
+        -vec3(self.yaw.sin(), 0.0, self.yaw.cos())
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(&Vec3::y())
+    }
+
+    // Zoom in by a number of scrolled lines, out when negative
+    pub fn zoom(&mut self, lines: f32) {
+        self.target_distance = (self.target_distance * ZOOM_FACTOR.powf(lines))
+            .clamp(self.min_distance, self.max_distance);
+    }
+
+    // Move the focus point to a point on the ground
+    pub fn focus_on(&mut self, focus: Vec3) {
+        self.target_focus = vec3(focus.x, 0.0, focus.z);
+    }
+
+    // Where the rig is headed
+    pub fn target_view(&self) -> CameraView {
+        CameraView {
+            focus: self.target_focus,
+            yaw: self.target_yaw,
+            distance: self.target_distance,
+        }
+    }
+
+    // Head towards a view, turning the shorter way around
+    pub fn move_to(&mut self, view: CameraView) {
+        self.follow = None;
+        self.target_focus = view.focus;
+        let turn = (view.yaw - self.target_yaw).rem_euclid(TAU);
+        self.target_yaw += if turn > PI { turn - TAU } else { turn };
+        self.target_distance = view.distance.clamp(self.min_distance, self.max_distance);
+    }
+
+    pub fn store_bookmark(&mut self, i: usize) {
+        self.bookmarks[i] = Some(self.target_view());
+    }
+
+    pub fn recall_bookmark(&mut self, i: usize) {
+        if let Some(view) = self.bookmarks[i] {
+            self.move_to(view);
+        }
+    }
+
+    // Keep the focus point on the map
+    fn clamp_focus(&mut self) {
+        self.target_focus.x = self
+            .target_focus
+            .x
+            .clamp(self.bounds_min.x, self.bounds_max.x);
+        self.target_focus.z = self
+            .target_focus
+            .z
+            .clamp(self.bounds_min.y, self.bounds_max.y);
+    }
+
+    // Ease towards the targets by the same amount at any frame rate
+    fn ease(&mut self, dt: f32) {
+        let t = 1.0 - (-EASE_SHARPNESS * dt).exp();
+        self.focus += (self.target_focus - self.focus) * t;
+        self.yaw += (self.target_yaw - self.yaw) * t;
+        self.distance += (self.target_distance - self.distance) * t;
+    }
+
+    // Place the camera. Orthographic cameras show as much of the ground
+    // around the focus point as a perspective camera would.
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.look_at(self.eye(), self.focus);
+        camera.set_ortho_extent(self.distance * (FOV / 2.0).tan());
     }
 }
 
-pub fn camera_system(world: &mut World, pressed_keys: &HashSet<VirtualKeyCode>, camera_entity: Entity) {
-    let mut camera = world.get::<&mut Camera>(camera_entity).unwrap();
+// Move the camera rig with the keyboard and mouse, `dt` is the time since the
+// last frame in seconds
+pub fn camera_system(
+    world: &mut World,
+    pressed_keys: &HashSet<VirtualKeyCode>,
+    mouse: &mut Mouse,
+    dt: f32,
+    camera_entity: Entity,
+) {
+    // Position of the followed entity, if it still exists
+    let follow = world.get::<&CameraRig>(camera_entity).unwrap().follow;
+    let followed = follow.and_then(|entity| {
+        world
+            .get::<&Transformation>(entity)
+            .ok()
+            .map(|transformation| transformation.pos)
+    });
+
+    let (camera, rig) = world
+        .query_one_mut::<(&mut Camera, &mut CameraRig)>(camera_entity)
+        .unwrap();
+    let pressed = |keys: &[VirtualKeyCode]| keys.iter().any(|key| pressed_keys.contains(key));
 
-    if pressed_keys.contains(&VirtualKeyCode::W) {
-        camera.position += vec3(-0.001, 0.0, -0.001);
-        camera.update_view();
+    // Pan sideways along x and forwards along y, with the keys or by holding
+    // the cursor at the edge of the window. Panning stops following.
+    let mut pan = mouse.edge(EDGE_MARGIN);
+    if pressed(&[VirtualKeyCode::W, VirtualKeyCode::Up]) {
+        pan.y += 1.0;
+    }
+    if pressed(&[VirtualKeyCode::S, VirtualKeyCode::Down]) {
+        pan.y -= 1.0;
+    }
+    if pressed(&[VirtualKeyCode::A, VirtualKeyCode::Left]) {
+        pan.x -= 1.0;
+    }
+    if pressed(&[VirtualKeyCode::D, VirtualKeyCode::Right]) {
+        pan.x += 1.0;
+    }
+    if pan != Vec2::zeros() {
+        let pan = vec2(pan.x.clamp(-1.0, 1.0), pan.y.clamp(-1.0, 1.0)).normalize();
+        rig.follow = None;
+        rig.target_focus +=
+            (rig.right() * pan.x + rig.forward() * pan.y) * PAN_SPEED * rig.distance * dt;
+    } else if let Some(pos) = followed {
+        rig.focus_on(pos);
+    } else {
+        rig.follow = None;
     }
 
-    if pressed_keys.contains(&VirtualKeyCode::S) {
-        camera.position += vec3(0.001, 0.0, 0.001);
-        camera.update_view();
+    // Rotate around the focus point
+    if pressed(&[VirtualKeyCode::Q]) {
+        rig.target_yaw += ROTATE_SPEED * dt;
     }
+    if pressed(&[VirtualKeyCode::E]) {
+        rig.target_yaw -= ROTATE_SPEED * dt;
+    }
+    let motion = mouse.take_motion();
+    if mouse.button_state.middle_pressed {
+        rig.target_yaw -= motion.x * DRAG_ROTATE_SPEED;
+    }
+
+    rig.clamp_focus();
+    rig.ease(dt);
+    rig.apply(camera);
+}
+
+// Camera keys: Ctrl+F1 to F4 store a bookmark and F1 to F4 recall it, F
+// follows the selected unit or stops following
+pub fn camera_key_system(
+    world: &mut World,
+    keycode: VirtualKeyCode,
+    modifiers: ModifiersState,
+    selected: &HashSet<Entity>,
+    camera_entity: Entity,
+) {
+    let mut rig = world.get::<&mut CameraRig>(camera_entity).unwrap();
+    let bookmark = [
+        VirtualKeyCode::F1,
+        VirtualKeyCode::F2,
+        VirtualKeyCode::F3,
+        VirtualKeyCode::F4,
+    ]
+    .iter()
+    .position(|&key| key == keycode);
 
-    if pressed_keys.contains(&VirtualKeyCode::A) {
-        camera.position += vec3(-0.001, 0.0, 0.001);
-        camera.update_view();
+    match bookmark {
+        Some(i) if modifiers.ctrl() => rig.store_bookmark(i),
+        Some(i) => rig.recall_bookmark(i),
+        None if keycode == VirtualKeyCode::F => {
+            rig.follow = match rig.follow {
+                Some(_) => None,
+                // Any unit of the selection, the same one every time
+                None => selected.iter().min().copied(),
+            };
+        }
+        None => {}
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rig() -> CameraRig {
+        CameraRig::new(Vec3::zeros(), 0.0, 20.0, vec2(-10.0, -5.0), vec2(10.0, 5.0))
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn focus_stays_within_the_bounds() {
+        let mut rig = rig();
+        rig.focus_on(vec3(30.0, 4.0, -8.0));
+        rig.clamp_focus();
+        assert_eq!(rig.target_focus, vec3(10.0, 0.0, -5.0));
+
+        rig.focus_on(vec3(-3.0, 0.0, 2.0));
+        rig.clamp_focus();
+        assert_eq!(rig.target_focus, vec3(-3.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn zoom_stays_within_its_limits() {
+        let mut rig = rig();
+        rig.zoom(1.0);
+        assert_close(rig.target_distance, 20.0 * ZOOM_FACTOR);
+        rig.zoom(100.0);
+        assert_eq!(rig.target_distance, rig.min_distance);
+        rig.zoom(-100.0);
+        assert_eq!(rig.target_distance, rig.max_distance);
+        // Only the target jumps, the distance eases towards it
+        assert_eq!(rig.distance, 20.0);
+    }
+
+    #[test]
+    fn easing_does_not_depend_on_the_frame_rate() {
+        let mut slow = rig();
+        let mut fast = rig();
+        for rig in [&mut slow, &mut fast] {
+            rig.focus_on(vec3(8.0, 0.0, -4.0));
+            rig.target_yaw = 1.0;
+            rig.zoom(-3.0);
+        }
+
+        slow.ease(0.1);
+        for _ in 0..10 {
+            fast.ease(0.01);
+        }
+        assert!((slow.focus - fast.focus).norm() < 1e-4);
+        assert_close(slow.yaw, fast.yaw);
+        assert_close(slow.distance, fast.distance);
+
+        // Part of the way there, and there after a while
+        assert!(slow.focus.x > 0.0 && slow.focus.x < 8.0);
+        for _ in 0..100 {
+            slow.ease(0.05);
+        }
+        assert!((slow.focus - slow.target_focus).norm() < 1e-3);
+        assert_close(slow.yaw, 1.0);
+    }
+
+    #[test]
+    fn orthographic_extent_follows_the_zoom() {
+        let mut camera = Camera::new(0.1, 50.0, 800, 600);
+        camera.set_kind(ProjectionKind::Orthographic);
+        let mut rig = rig();
+        rig.apply(&mut camera);
+        let far = camera.projection;
+
+        rig.distance = 10.0;
+        rig.apply(&mut camera);
+        // Half the distance shows half as much ground, so things look twice as big
+        assert_close(camera.projection[(0, 0)], far[(0, 0)] * 2.0);
+        assert_close(camera.projection[(1, 1)], far[(1, 1)] * 2.0);
+        assert_close(1.0 / camera.projection[(1, 1)], 10.0 * (FOV / 2.0).tan());
+
+        // Back to perspective, the zoom moves the camera instead
+        camera.toggle_kind();
+        assert_eq!(camera.kind(), ProjectionKind::Perspective);
+        assert_close(camera.projection[(1, 1)], 1.0 / (FOV / 2.0).tan());
+    }
+
+    #[test]
+    fn move_to_turns_the_shorter_way() {
+        let mut rig = rig();
+        rig.target_yaw = 0.3;
+        let view = |yaw: f32| CameraView {
+            focus: vec3(1.0, 0.0, 2.0),
+            yaw,
+            distance: 100.0,
+        };
+
+        // Just short of a full turn is a small turn backwards
+        rig.move_to(view(TAU - 0.2));
+        assert_close(rig.target_yaw, -0.2);
+        // Angles beyond a full turn end up at the same heading
+        rig.move_to(view(0.5 + 2.0 * TAU));
+        assert_close(rig.target_yaw, 0.5);
+        assert_eq!(rig.target_focus, vec3(1.0, 0.0, 2.0));
+        assert_eq!(rig.target_distance, rig.max_distance);
+    }
+
+    #[test]
+    fn bookmarks_recall_the_stored_view() {
+        let mut rig = rig();
+        rig.focus_on(vec3(3.0, 0.0, -2.0));
+        rig.target_yaw = 1.0;
+        rig.zoom(2.0);
+        let stored = rig.target_view();
+        rig.store_bookmark(2);
+
+        rig.focus_on(vec3(-7.0, 0.0, 4.0));
+        rig.target_yaw = -2.0;
+        rig.zoom(-2.0);
+        rig.follow = Some(Entity::DANGLING);
+        rig.recall_bookmark(2);
+        assert_eq!(rig.target_focus, stored.focus);
+        assert_close(rig.target_yaw, stored.yaw);
+        assert_close(rig.target_distance, stored.distance);
+        // Recalling a bookmark stops following
+        assert_eq!(rig.follow, None);
 
-    if pressed_keys.contains(&VirtualKeyCode::D) {
-        camera.position += vec3(0.001, 0.0, -0.001);
-        camera.update_view();
+        // Empty slots leave the camera alone
+        rig.focus_on(Vec3::zeros());
+        rig.recall_bookmark(0);
+        assert_eq!(rig.target_focus, Vec3::zeros());
     }
 }
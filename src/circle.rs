@@ -1,9 +0,0 @@
-use nalgebra_glm::Vec2;
-
-use crate::vertex::Vertex;
-
-pub fn generate_circle_vertices(pos: Vec2, r: f32) -> Vec<Vertex> {
-
-
-    todo!();
-}
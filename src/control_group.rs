@@ -0,0 +1,184 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use glium::glutin::event::{ModifiersState, VirtualKeyCode};
+use hecs::{Entity, World};
+use nalgebra_glm::Vec3;
+
+use crate::{camera::CameraRig, selectable::Selectable, transformation::Transformation};
+
+// Two presses of the same group key within this time move the camera to the group
+const DOUBLE_TAP_TIME: Duration = Duration::from_millis(300);
+
+const GROUP_KEYS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Key0,
+];
+
+// Numbered groups of units. Ctrl and a number key assigns the selection to a
+// group, the number key selects the group again.
+#[derive(Default)]
+pub struct ControlGroups {
+    groups: [Vec<Entity>; GROUP_KEYS.len()],
+    // Group key pressed last and when
+    last_tap: Option<(usize, Instant)>,
+}
+
+impl ControlGroups {
+    // Remember a press of a group key at `now`, returns whether it is the
+    // second press of the same key within `DOUBLE_TAP_TIME`
+    fn tap(&mut self, i: usize, now: Instant) -> bool {
+        let double_tap = matches!(
+            self.last_tap,
+            Some((last, time)) if last == i && now - time < DOUBLE_TAP_TIME
+        );
+        self.last_tap = Some((i, now));
+        double_tap
+    }
+}
+
+// Middle of the ground positions of the entities, None without any
+pub fn centroid<'a>(world: &World, entities: impl IntoIterator<Item = &'a Entity>) -> Option<Vec3> {
+    let positions: Vec<Vec3> = entities
+        .into_iter()
+        .filter_map(|&entity| world.get::<&Transformation>(entity).ok().map(|t| t.pos))
+        .collect();
+    if positions.is_empty() {
+        return None;
+    }
+    Some(positions.iter().sum::<Vec3>() / positions.len() as f32)
+}
+
+// Assign or select a control group with the number keys. Selecting the same
+// group twice in a row quickly moves the camera to the middle of it.
+pub fn control_group_system(
+    world: &mut World,
+    groups: &mut ControlGroups,
+    selected: &mut HashSet<Entity>,
+    keycode: VirtualKeyCode,
+    modifiers: ModifiersState,
+    camera_entity: Entity,
+) {
+    let Some(i) = GROUP_KEYS.iter().position(|&key| key == keycode) else {
+        return;
+    };
+
+    if modifiers.ctrl() {
+        let mut group: Vec<Entity> = selected.iter().copied().collect();
+        group.sort();
+        groups.groups[i] = group;
+        groups.last_tap = None;
+        return;
+    }
+
+    // Units that died since the group was assigned are left out
+    groups.groups[i].retain(|&entity| world.contains(entity));
+    selected.clear();
+    for (id, (selectable,)) in world.query_mut::<(&mut Selectable,)>() {
+        selectable.selected = groups.groups[i].contains(&id);
+        if selectable.selected {
+            selected.insert(id);
+        }
+    }
+
+    if groups.tap(i, Instant::now()) {
+        if let Some(center) = centroid(world, &groups.groups[i]) {
+            let mut rig = world.get::<&mut CameraRig>(camera_entity).unwrap();
+            rig.follow = None;
+            rig.focus_on(center);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{vec2, vec3};
+
+    use super::*;
+    use crate::bounding_circle::BoundingCircle;
+
+    #[test]
+    fn centroid_of_positions() {
+        let mut world = World::new();
+        let a = world.spawn((Transformation::new(vec3(1.0, 0.0, 2.0), 0.0, 1.0),));
+        let b = world.spawn((Transformation::new(vec3(3.0, 0.0, -4.0), 0.0, 1.0),));
+        let without_position = world.spawn(());
+        let dead = world.spawn((Transformation::new(vec3(100.0, 0.0, 0.0), 0.0, 1.0),));
+        world.despawn(dead).unwrap();
+
+        assert_eq!(
+            centroid(&world, &[a, b, without_position, dead]),
+            Some(vec3(2.0, 0.0, -1.0))
+        );
+        assert_eq!(centroid(&world, &[without_position, dead]), None);
+        assert_eq!(centroid(&world, &[]), None);
+    }
+
+    #[test]
+    fn double_taps_are_quick_presses_of_the_same_key() {
+        let mut groups = ControlGroups::default();
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        assert!(!groups.tap(0, at(0)));
+        assert!(groups.tap(0, at(299)));
+        // Too late
+        assert!(!groups.tap(0, at(700)));
+        // Another key in between
+        assert!(!groups.tap(1, at(800)));
+        assert!(!groups.tap(0, at(900)));
+        assert!(groups.tap(0, at(1000)));
+    }
+
+    #[test]
+    fn double_tap_moves_the_camera_to_the_group() {
+        let mut world = World::new();
+        let rig = CameraRig::new(
+            Vec3::zeros(),
+            0.0,
+            20.0,
+            vec2(-10.0, -10.0),
+            vec2(10.0, 10.0),
+        );
+        let camera_entity = world.spawn((rig,));
+        let unit = world.spawn((
+            Transformation::new(vec3(4.0, 0.0, 6.0), 0.0, 1.0),
+            Selectable::new(BoundingCircle {
+                r: 1.0,
+                ground_pos: vec2(0.0, 0.0),
+            }),
+        ));
+
+        let mut groups = ControlGroups::default();
+        let mut selected = HashSet::from([unit]);
+        let mut press = |world: &mut World, modifiers| {
+            control_group_system(
+                world,
+                &mut groups,
+                &mut selected,
+                VirtualKeyCode::Key3,
+                modifiers,
+                camera_entity,
+            )
+        };
+        press(&mut world, ModifiersState::CTRL);
+        press(&mut world, ModifiersState::empty());
+        let target = world.get::<&CameraRig>(camera_entity).unwrap().target_focus;
+        assert_eq!(target, Vec3::zeros());
+
+        press(&mut world, ModifiersState::empty());
+        let target = world.get::<&CameraRig>(camera_entity).unwrap().target_focus;
+        assert_eq!(target, vec3(4.0, 0.0, 6.0));
+        assert!(world.get::<&Selectable>(unit).unwrap().selected);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+use nalgebra_glm::{vec3, Vec3};
+
+use crate::renderer::Renderer;
+
+// Immediate mode debug drawing. Any system given the `DebugDraw` can queue
+// shapes in world space during a frame, they are drawn on top of the scene
+// when the frame is rendered. Shapes queued with a zero duration are drawn
+// for one frame, others stay until their duration has passed.
+
+pub const RED: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+pub const GREEN: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+pub const BLUE: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+pub const YELLOW: Vec3 = Vec3::new(1.0, 1.0, 0.0);
+pub const CYAN: Vec3 = Vec3::new(0.0, 1.0, 1.0);
+pub const MAGENTA: Vec3 = Vec3::new(1.0, 0.0, 1.0);
+pub const WHITE: Vec3 = Vec3::new(1.0, 1.0, 1.0);
+
+// Segments of a full circle
+const CIRCLE_SEGMENTS: usize = 32;
+
+struct DebugLine {
+    line: [Vec3; 2],
+    color: Vec3,
+    // Lines without an expiry time are only drawn once
+    expires: Option<Instant>,
+}
+
+// Lines queued for the current frame and lines that haven't expired yet
+#[derive(Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+}
+
+impl DebugDraw {
+    fn queue(
+        &mut self,
+        lines: impl IntoIterator<Item = [Vec3; 2]>,
+        color: Vec3,
+        duration: Duration,
+    ) {
+        let expires = (!duration.is_zero()).then(|| Instant::now() + duration);
+        self.lines.extend(lines.into_iter().map(|line| DebugLine {
+            line,
+            color,
+            expires,
+        }));
+    }
+
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: Vec3, duration: Duration) {
+        self.queue([[from, to]], color, duration);
+    }
+
+    // Lines through every point in order
+    pub fn polyline(&mut self, points: &[Vec3], color: Vec3, duration: Duration) {
+        self.queue(
+            points.windows(2).map(|pair| [pair[0], pair[1]]),
+            color,
+            duration,
+        );
+    }
+
+    // Three lines of length `size` crossing at `center`, one along each axis
+    pub fn cross(&mut self, center: Vec3, size: f32, color: Vec3, duration: Duration) {
+        let half = size / 2.0;
+        self.queue(
+            [Vec3::x(), Vec3::y(), Vec3::z()]
+                .map(|axis| [center - axis * half, center + axis * half]),
+            color,
+            duration,
+        );
+    }
+
+    // Circle lying flat, parallel to the ground
+    pub fn circle(&mut self, center: Vec3, radius: f32, color: Vec3, duration: Duration) {
+        let point = |i: usize| {
+            let angle = 2.0 * PI * i as f32 / CIRCLE_SEGMENTS as f32;
+            center + vec3(angle.cos(), 0.0, angle.sin()) * radius
+        };
+        self.queue(
+            (0..CIRCLE_SEGMENTS).map(|i| [point(i), point(i + 1)]),
+            color,
+            duration,
+        );
+    }
+
+    // Edges of an axis aligned bounding box
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Vec3, duration: Duration) {
+        let corner = |i: usize| {
+            vec3(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        // Corners whose index differs in exactly one bit share an edge
+        let edges = (0..8).flat_map(|i| {
+            [1, 2, 4]
+                .into_iter()
+                .filter(move |bit| i & bit == 0)
+                .map(move |bit| [corner(i), corner(i | bit)])
+        });
+        self.queue(edges, color, duration);
+    }
+
+    // Line from `from` to `to` with a head at `to`
+    pub fn arrow(&mut self, from: Vec3, to: Vec3, color: Vec3, duration: Duration) {
+        let length = (to - from).norm();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = (to - from) / length;
+
+        // The head opens sideways, or along x for vertical arrows
+        let side = direction.cross(&Vec3::y());
+        let side = if side.norm() > 1e-3 {
+            side.normalize()
+        } else {
+            Vec3::x()
+        };
+        let head = (length * 0.25).min(0.5);
+        let back = to - direction * head;
+
+        self.queue(
+            [
+                [from, to],
+                [to, back + side * head * 0.5],
+                [to, back - side * head * 0.5],
+            ],
+            color,
+            duration,
+        );
+    }
+
+    // Submit every queued line. Submitting again, like for a screenshot of
+    // the same frame, draws the same lines.
+    pub fn submit(&self, renderer: &mut impl Renderer) {
+        for shape in self.lines.chunk_by(|a, b| a.color == b.color) {
+            let segments: Vec<[Vec3; 2]> = shape.iter().map(|line| line.line).collect();
+            renderer.submit_lines(&segments, shape[0].color);
+        }
+    }
+
+    // Drop the lines that were only meant for the frame that was just
+    // presented or have expired
+    pub fn end_frame(&mut self) {
+        let now = Instant::now();
+        self.lines
+            .retain(|line| line.expires.is_some_and(|expires| expires > now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{DrawCommand, RecordingRenderer};
+
+    fn submitted(debug_draw: &DebugDraw) -> Vec<DrawCommand> {
+        let mut renderer = RecordingRenderer::default();
+        debug_draw.submit(&mut renderer);
+        renderer.commands
+    }
+
+    #[test]
+    fn lines_stay_until_the_frame_ends() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.line(Vec3::zeros(), Vec3::x(), RED, Duration::ZERO);
+        debug_draw.line(Vec3::zeros(), Vec3::y(), GREEN, Duration::from_secs(60));
+
+        // A screenshot of the frame draws the same lines as the window
+        let first = submitted(&debug_draw);
+        assert_eq!(first.len(), 2);
+        assert_eq!(submitted(&debug_draw), first);
+
+        debug_draw.end_frame();
+        assert_eq!(
+            submitted(&debug_draw),
+            [DrawCommand::Lines {
+                lines: vec![[Vec3::zeros(), Vec3::y()]],
+                color: GREEN,
+            }]
+        );
+    }
+
+    #[test]
+    fn shapes_of_one_color_are_submitted_together() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.aabb(Vec3::zeros(), Vec3::repeat(1.0), BLUE, Duration::ZERO);
+        debug_draw.cross(Vec3::zeros(), 1.0, BLUE, Duration::ZERO);
+
+        match submitted(&debug_draw).as_slice() {
+            [DrawCommand::Lines { lines, color }] => {
+                assert_eq!(*color, BLUE);
+                assert_eq!(lines.len(), 12 + 3);
+            }
+            commands => panic!("unexpected commands {:?}", commands),
+        }
+    }
+}
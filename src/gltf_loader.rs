@@ -0,0 +1,514 @@
+use std::{collections::HashMap, fmt, path::Path, rc::Rc};
+
+use gltf::{
+    image::{Format, Source},
+    mesh::Mode,
+    Document, Node,
+};
+use image::RgbaImage;
+use nalgebra_glm::{Mat3, Mat4, Vec3};
+
+use crate::{
+    material::{Material, TextureSource},
+    mesh_data::{Bounds, MeshData, PartData, SubMeshData},
+    normals,
+    vertex::{self, Vertex},
+};
+
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(gltf::Error),
+    NoScene,
+    MissingPositions {
+        mesh: String,
+    },
+    UnsupportedMode {
+        mesh: String,
+        mode: Mode,
+    },
+    UnsupportedImageFormat {
+        image: usize,
+        format: Format,
+    },
+    // An attribute of a primitive has a different number of values than its positions
+    AccessorMismatch {
+        mesh: String,
+        attribute: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    IndexOutOfRange {
+        mesh: String,
+        index: u32,
+        vertices: usize,
+    },
+    ImageTooShort {
+        image: usize,
+    },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Gltf(err) => write!(f, "failed to read glTF: {}", err),
+            GltfError::NoScene => write!(f, "file contains no scene"),
+            GltfError::MissingPositions { mesh } => {
+                write!(f, "mesh '{}' has a primitive without positions", mesh)
+            }
+            GltfError::UnsupportedMode { mesh, mode } => {
+                write!(
+                    f,
+                    "mesh '{}' has unsupported primitive mode {:?}",
+                    mesh, mode
+                )
+            }
+            GltfError::UnsupportedImageFormat { image, format } => {
+                write!(
+                    f,
+                    "image {} has unsupported pixel format {:?}",
+                    image, format
+                )
+            }
+            GltfError::AccessorMismatch {
+                mesh,
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "mesh '{}' has {} {} values for {} positions",
+                mesh, found, attribute, expected
+            ),
+            GltfError::IndexOutOfRange {
+                mesh,
+                index,
+                vertices,
+            } => write!(
+                f,
+                "mesh '{}' refers to vertex {} but has {} vertices",
+                mesh, index, vertices
+            ),
+            GltfError::ImageTooShort { image } => {
+                write!(f, "image {} has less data than its size", image)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Gltf(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for GltfError {
+    fn from(err: gltf::Error) -> Self {
+        GltfError::Gltf(err)
+    }
+}
+
+// State shared while walking the node hierarchy of a scene
+struct Loader<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    parts: Vec<PartData>,
+}
+
+impl Loader<'_> {
+    // Add a part for the node and its descendants. Vertices are transformed to
+    // model space, so parts line up without knowing about the node transforms.
+    fn add_node(
+        &mut self,
+        node: Node,
+        parent: Option<usize>,
+        parent_transform: &Mat4,
+    ) -> Result<(), GltfError> {
+        let transform = parent_transform * Mat4::from(node.transform().matrix());
+        let name = match (node.name(), node.mesh()) {
+            (Some(name), _) => name.to_string(),
+            (None, Some(mesh)) if mesh.name().is_some() => mesh.name().unwrap().to_string(),
+            _ => format!("node{}", node.index()),
+        };
+
+        let mut submeshes = vec![];
+        if let Some(mesh) = node.mesh() {
+            let mesh_name = mesh.name().map_or(name.clone(), str::to_string);
+            for primitive in mesh.primitives() {
+                let start = self.indices.len();
+                self.add_primitive(&primitive, &mesh_name, &transform)?;
+                submeshes.push(SubMeshData {
+                    material: primitive.material().index(),
+                    range: start..self.indices.len(),
+                });
+            }
+        }
+
+        let part = self.parts.len();
+        self.parts.push(PartData {
+            name,
+            parent,
+            submeshes,
+        });
+
+        for child in node.children() {
+            self.add_node(child, Some(part), &transform)?;
+        }
+        Ok(())
+    }
+
+    fn add_primitive(
+        &mut self,
+        primitive: &gltf::Primitive,
+        mesh_name: &str,
+        transform: &Mat4,
+    ) -> Result<(), GltfError> {
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .ok_or_else(|| GltfError::MissingPositions {
+                mesh: mesh_name.to_string(),
+            })?
+            .collect();
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+        let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+            Some(tex_coords) => tex_coords.into_f32().collect(),
+            None => vec![[0.0, 0.0]; positions.len()],
+        };
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let check_count = |attribute: &'static str, found: usize| {
+            if found == positions.len() {
+                Ok(())
+            } else {
+                Err(GltfError::AccessorMismatch {
+                    mesh: mesh_name.to_string(),
+                    attribute,
+                    expected: positions.len(),
+                    found,
+                })
+            }
+        };
+        if let Some(normals) = &normals {
+            check_count("NORMAL", normals.len())?;
+        }
+        check_count("TEXCOORD_0", tex_coords.len())?;
+        if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+            return Err(GltfError::IndexOutOfRange {
+                mesh: mesh_name.to_string(),
+                index,
+                vertices: positions.len(),
+            });
+        }
+
+        let mut indices = match primitive.mode() {
+            Mode::Triangles => indices,
+            Mode::TriangleStrip => (2..indices.len())
+                .flat_map(|i| match i % 2 {
+                    0 => [indices[i - 2], indices[i - 1], indices[i]],
+                    _ => [indices[i - 1], indices[i - 2], indices[i]],
+                })
+                .collect(),
+            Mode::TriangleFan => (2..indices.len())
+                .flat_map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            mode => {
+                return Err(GltfError::UnsupportedMode {
+                    mesh: mesh_name.to_string(),
+                    mode,
+                })
+            }
+        };
+
+        // Normals are transformed by the inverse transpose so non-uniform scaling keeps them perpendicular
+        let normal_transform = nalgebra_glm::mat4_to_mat3(transform)
+            .try_inverse()
+            .unwrap_or_else(Mat3::identity)
+            .transpose();
+        let mut vertices: Vec<Vertex> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, position)| {
+                let position = transform.transform_point(&Vec3::from(*position).into());
+                let normal = match &normals {
+                    Some(normals) => (normal_transform * Vec3::from(normals[i])).normalize(),
+                    None => Vec3::y(),
+                };
+                Vertex {
+                    position: position.coords.into(),
+                    normal: normal.into(),
+                    texture_coord: tex_coords[i],
+                }
+            })
+            .collect();
+
+        // Without normals the spec asks for flat shading, which needs a vertex per corner
+        if normals.is_none() {
+            let mut expanded: Vec<Vertex> = indices.iter().map(|&i| vertices[i as usize]).collect();
+            normals::flat(&mut expanded);
+            (vertices, indices) = vertex::deduplicate(&expanded);
+        }
+
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend(vertices);
+        self.indices.extend(indices.iter().map(|&i| i + offset));
+        Ok(())
+    }
+}
+
+// Convert the decoded pixels of an embedded image to RGBA, 16 bit channels are
+// reduced to their high byte
+fn to_rgba(index: usize, data: gltf::image::Data) -> Result<RgbaImage, GltfError> {
+    let channels = match data.format {
+        Format::R8 | Format::R16 => 1,
+        Format::R8G8 | Format::R16G16 => 2,
+        Format::R8G8B8 | Format::R16G16B16 => 3,
+        Format::R8G8B8A8 | Format::R16G16B16A16 => 4,
+        format => {
+            return Err(GltfError::UnsupportedImageFormat {
+                image: index,
+                format,
+            })
+        }
+    };
+    let bytes_per_channel = match data.format {
+        Format::R8 | Format::R8G8 | Format::R8G8B8 | Format::R8G8B8A8 => 1,
+        _ => 2,
+    };
+
+    let pixels: Vec<u8> = data
+        .pixels
+        .chunks_exact(channels * bytes_per_channel)
+        .flat_map(|pixel| {
+            let channel = |c: usize| match bytes_per_channel {
+                1 => pixel[c],
+                _ => (u16::from_ne_bytes([pixel[2 * c], pixel[2 * c + 1]]) >> 8) as u8,
+            };
+            // One and two channel images are grey, with alpha in the second channel
+            match channels {
+                1 => [channel(0), channel(0), channel(0), 255],
+                2 => [channel(0), channel(0), channel(0), channel(1)],
+                3 => [channel(0), channel(1), channel(2), 255],
+                _ => [channel(0), channel(1), channel(2), channel(3)],
+            }
+        })
+        .collect();
+    RgbaImage::from_raw(data.width, data.height, pixels)
+        .ok_or(GltfError::ImageTooShort { image: index })
+}
+
+// Images stored in files next to the model are loaded like .mtl textures,
+// images embedded in a buffer or data uri are decoded here
+fn load_textures(
+    document: &Document,
+    buffers: &[gltf::buffer::Data],
+    dir: &Path,
+) -> Result<Vec<TextureSource>, GltfError> {
+    document
+        .images()
+        .map(|image| match image.source() {
+            Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                Ok(TextureSource::File(dir.join(uri)))
+            }
+            source => {
+                let data = gltf::image::Data::from_source(source, Some(dir), buffers)?;
+                Ok(TextureSource::Image(Rc::new(to_rgba(image.index(), data)?)))
+            }
+        })
+        .collect()
+}
+
+// Approximate a metallic-roughness material with the Phong parameters the shader uses
+fn load_material(material: gltf::Material, textures: &[TextureSource]) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+    let base_color = Vec3::new(r, g, b);
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+
+    let texture = |texture: gltf::Texture| textures[texture.source().index()].clone();
+    Material {
+        name: material.name().unwrap_or_default().to_string(),
+        ambient: base_color,
+        diffuse: base_color,
+        // Dielectrics reflect about 4% of light, metals reflect in their base color
+        specular: Vec3::repeat(0.04).lerp(&base_color, metallic),
+        // Blinn-Phong exponent matching the width of the GGX highlight
+        shininess: (2.0 / roughness.powi(4).max(1e-4) - 2.0).clamp(1.0, 256.0),
+        emissive: Vec3::from(material.emissive_factor()),
+        dissolve: a,
+        illum: 2,
+        diffuse_map: pbr.base_color_texture().map(|info| texture(info.texture())),
+        bump_map: material
+            .normal_texture()
+            .map(|info| texture(info.texture())),
+    }
+}
+
+// Load a .gltf file with embedded or external buffers, or a binary .glb file.
+// Every node of the default scene becomes a part named after the node, with the
+// primitives of its mesh as submeshes.
+pub fn load(path: &Path) -> Result<MeshData, GltfError> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let buffers = gltf::import_buffers(&document, Some(dir), blob)?;
+    let dependencies = document
+        .buffers()
+        .filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:") => Some(dir.join(uri)),
+            _ => None,
+        })
+        .collect();
+
+    let textures = load_textures(&document, &buffers, dir)?;
+    let materials = document
+        .materials()
+        .map(|material| load_material(material, &textures))
+        .collect();
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or(GltfError::NoScene)?;
+
+    let mut loader = Loader {
+        buffers: &buffers,
+        vertices: vec![],
+        indices: vec![],
+        parts: vec![],
+    };
+    for node in scene.nodes() {
+        loader.add_node(node, None, &Mat4::identity())?;
+    }
+
+    // Nodes reached through several parents would otherwise share a name
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for part in &mut loader.parts {
+        let count = seen.entry(part.name.clone()).or_default();
+        if *count > 0 {
+            part.name = format!("{}.{}", part.name, count);
+        }
+        *count += 1;
+    }
+
+    Ok(MeshData {
+        bounds: Bounds::from_vertices(&loader.vertices),
+        vertices: loader.vertices,
+        indices: loader.indices,
+        materials,
+        parts: loader.parts,
+        dependencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_test(name: &str) -> Result<MeshData, GltfError> {
+        load(&Path::new("testdata").join(name))
+    }
+
+    #[test]
+    fn nodes_become_named_parts() {
+        let data = load_test("hierarchy.gltf").unwrap();
+        let parts: Vec<_> = data
+            .parts
+            .iter()
+            .map(|part| (part.name.as_str(), part.parent))
+            .collect();
+        assert_eq!(parts, [("Hull", None), ("Turret", Some(0))]);
+        let names: Vec<_> = data.materials.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Red", "Blue"]);
+    }
+
+    #[test]
+    fn primitives_become_submeshes() {
+        let data = load_test("hierarchy.gltf").unwrap();
+        let submeshes: Vec<Vec<_>> = data
+            .parts
+            .iter()
+            .map(|part| {
+                part.submeshes
+                    .iter()
+                    .map(|submesh| (submesh.material, submesh.range.clone()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            submeshes,
+            [
+                vec![(Some(0), 0..3), (Some(1), 3..6)],
+                vec![(Some(1), 6..9)]
+            ]
+        );
+    }
+
+    #[test]
+    fn node_transforms_are_baked_in() {
+        let data = load_test("hierarchy.gltf").unwrap();
+        let positions = |part: usize| -> Vec<[f32; 3]> {
+            let range = data.parts[part].submeshes[0].range.clone();
+            data.indices[range]
+                .iter()
+                .map(|&i| data.vertices[i as usize].position)
+                .collect()
+        };
+        // The hull is moved by its own translation, the turret by both
+        assert_eq!(
+            positions(0),
+            [[10.0, 0.0, 0.0], [10.0, 0.0, 1.0], [11.0, 0.0, 0.0]]
+        );
+        assert_eq!(
+            positions(1),
+            [[10.0, 2.0, 0.0], [10.0, 2.0, 1.0], [11.0, 2.0, 0.0]]
+        );
+        assert_eq!(data.bounds.min, Vec3::new(10.0, 0.0, 0.0));
+        assert_eq!(data.bounds.max, Vec3::new(11.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn mismatched_attributes_are_rejected() {
+        assert!(matches!(
+            load_test("mismatched_normals.gltf"),
+            Err(GltfError::AccessorMismatch {
+                attribute: "NORMAL",
+                expected: 3,
+                found: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn indices_out_of_range_are_rejected() {
+        assert!(matches!(
+            load_test("index_out_of_range.gltf"),
+            Err(GltfError::IndexOutOfRange {
+                index: 5,
+                vertices: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn short_images_are_rejected() {
+        let data = gltf::image::Data {
+            pixels: vec![255; 4 * 3],
+            format: Format::R8G8B8A8,
+            width: 2,
+            height: 2,
+        };
+        assert!(matches!(
+            to_rgba(7, data),
+            Err(GltfError::ImageTooShort { image: 7 })
+        ));
+    }
+}
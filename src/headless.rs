@@ -0,0 +1,139 @@
+use std::{ffi::c_void, fmt, rc::Rc};
+
+use glium::{
+    backend::{Backend, Context},
+    debug::DebugCallbackBehavior,
+    IncompatibleOpenGl, SwapBuffersError,
+};
+use khronos_egl as egl;
+
+// EGL_PLATFORM_SURFACELESS_MESA, for a display that doesn't need a window system
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    // libEGL could not be loaded, the message says why
+    Load(String),
+    Egl(egl::Error),
+    NoConfig,
+    Incompatible(IncompatibleOpenGl),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Load(err) => write!(f, "failed to load libEGL: {}", err),
+            HeadlessError::Egl(err) => write!(f, "EGL error: {}", err),
+            HeadlessError::NoConfig => write!(f, "no EGL config supports OpenGL"),
+            HeadlessError::Incompatible(err) => write!(f, "unsupported OpenGL context: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HeadlessError::Egl(err) => Some(err),
+            HeadlessError::Incompatible(err) => Some(err),
+            HeadlessError::Load(_) | HeadlessError::NoConfig => None,
+        }
+    }
+}
+
+impl From<egl::Error> for HeadlessError {
+    fn from(err: egl::Error) -> Self {
+        HeadlessError::Egl(err)
+    }
+}
+
+impl From<IncompatibleOpenGl> for HeadlessError {
+    fn from(err: IncompatibleOpenGl) -> Self {
+        HeadlessError::Incompatible(err)
+    }
+}
+
+// OpenGL context without a window or even a window system, using Mesa's
+// surfaceless EGL platform. It has no default framebuffer, everything is
+// rendered into offscreen framebuffers.
+struct Surfaceless {
+    egl: egl::DynamicInstance<egl::EGL1_5>,
+    display: egl::Display,
+    context: egl::Context,
+}
+
+unsafe impl Backend for Surfaceless {
+    fn swap_buffers(&self) -> Result<(), SwapBuffersError> {
+        Ok(())
+    }
+
+    unsafe fn get_proc_address(&self, symbol: &str) -> *const c_void {
+        self.egl
+            .get_proc_address(symbol)
+            .map_or(std::ptr::null(), |address| address as *const c_void)
+    }
+
+    fn get_framebuffer_dimensions(&self) -> (u32, u32) {
+        (0, 0)
+    }
+
+    fn is_current(&self) -> bool {
+        self.egl.get_current_context() == Some(self.context)
+    }
+
+    unsafe fn make_current(&self) {
+        self.egl
+            .make_current(self.display, None, None, Some(self.context))
+            .expect("Failed to make the headless context current");
+    }
+}
+
+// Create an OpenGL 3.3 core context that renders without a display, with a
+// hardware driver or Mesa's llvmpipe
+pub fn create_context() -> Result<Rc<Context>, HeadlessError> {
+    let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+        .map_err(|err| HeadlessError::Load(err.to_string()))?;
+
+    let display = unsafe {
+        egl.get_platform_display(
+            PLATFORM_SURFACELESS_MESA,
+            egl::DEFAULT_DISPLAY,
+            &[egl::ATTRIB_NONE],
+        )
+    }?;
+    egl.initialize(display)?;
+    egl.bind_api(egl::OPENGL_API)?;
+
+    // There are no surfaces to render to, so any surface type will do
+    let attributes = [
+        egl::SURFACE_TYPE,
+        0,
+        egl::RENDERABLE_TYPE,
+        egl::OPENGL_BIT,
+        egl::NONE,
+    ];
+    let config = egl
+        .choose_first_config(display, &attributes)?
+        .ok_or(HeadlessError::NoConfig)?;
+    let context = egl.create_context(
+        display,
+        config,
+        None,
+        &[
+            egl::CONTEXT_MAJOR_VERSION,
+            3,
+            egl::CONTEXT_MINOR_VERSION,
+            3,
+            egl::CONTEXT_OPENGL_PROFILE_MASK,
+            egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ],
+    )?;
+    egl.make_current(display, None, None, Some(context))?;
+
+    let backend = Surfaceless {
+        egl,
+        display,
+        context,
+    };
+    Ok(unsafe { Context::new(backend, true, DebugCallbackBehavior::Ignore) }?)
+}
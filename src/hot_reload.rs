@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use glium::backend::Facade;
+
+use crate::assets::Assets;
+
+// How often the files of loaded assets are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Watches the files assets were loaded from and reloads the assets in place
+// when a file changes. Failed reloads are printed and the old asset is kept,
+// so a shader with a typo doesn't take the game down.
+pub struct HotReloader {
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl Default for HotReloader {
+    fn default() -> Self {
+        HotReloader {
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+}
+
+impl HotReloader {
+    // Whether the file changed since it was last seen. Files are not reported
+    // as changed the first time they are seen.
+    fn changed(&mut self, path: &Path) -> bool {
+        let Ok(modified) = fs::metadata(path).and_then(|meta| meta.modified()) else {
+            return false;
+        };
+        match self.modified.insert(path.to_path_buf(), modified) {
+            Some(previous) => previous != modified,
+            None => false,
+        }
+    }
+
+    pub fn update(&mut self, display: &impl Facade, assets: &mut Assets) {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+
+        let meshes: Vec<_> = assets.meshes.keyed().collect();
+        for (path, handle) in meshes {
+            // The model and its material libraries, all checked so none keeps a
+            // stale modification time
+            let dependencies = assets.mesh(&handle).data.dependencies.clone();
+            let mut changed = self.changed(&path);
+            for dependency in &dependencies {
+                changed |= self.changed(dependency);
+            }
+            if changed {
+                match assets.reload_mesh(display, &handle) {
+                    Ok(()) => println!("Reloaded {}", path.display()),
+                    Err(err) => eprintln!("Failed to reload {}: {}", path.display(), err),
+                }
+            }
+        }
+
+        let textures: Vec<_> = assets.textures.keyed().collect();
+        for (path, handle) in textures {
+            if self.changed(&path) {
+                match assets.reload_texture(display, &handle) {
+                    Ok(()) => println!("Reloaded {}", path.display()),
+                    Err(err) => eprintln!("Failed to reload {}: {}", path.display(), err),
+                }
+            }
+        }
+
+        let shaders: Vec<_> = assets.shaders.keyed().collect();
+        for ((vertex, fragment), handle) in shaders {
+            // Check both files so neither keeps a stale modification time
+            let vertex_changed = self.changed(&vertex);
+            let fragment_changed = self.changed(&fragment);
+            if vertex_changed || fragment_changed {
+                let name = format!("{} and {}", vertex.display(), fragment.display());
+                match assets.reload_shader(display, &handle) {
+                    Ok(()) => println!("Reloaded {}", name),
+                    Err(err) => eprintln!("Failed to reload {}: {}", name, err),
+                }
+            }
+        }
+    }
+}
@@ -1,15 +1,286 @@
+use std::sync::LazyLock;
+
+use glium::uniforms::{UniformValue, Uniforms};
 use nalgebra_glm::Vec3;
 
+// Most lights a draw is lit by, has to match MAX_LIGHTS in fragment.glsl
+pub const MAX_LIGHTS: usize = 16;
+
+// How the light of point and spot lights falls off over distance `d`:
+// 1 / (constant + linear * d + quadratic * d^2)
+#[derive(Clone, Copy, Debug)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub const NONE: Attenuation = Attenuation {
+        constant: 1.0,
+        linear: 0.0,
+        quadratic: 0.0,
+    };
+
+    // Falloff that makes the light fade out at around `range`
+    pub fn with_range(range: f32) -> Self {
+        Attenuation {
+            constant: 1.0,
+            linear: 4.5 / range,
+            quadratic: 75.0 / (range * range),
+        }
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Attenuation::with_range(50.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    // Light from far away, like the sun, shining in `direction` everywhere
+    Directional {
+        direction: Vec3,
+    },
+    Point {
+        position: Vec3,
+        attenuation: Attenuation,
+    },
+    // Cone of light, full strength within `inner_angle` of `direction` and
+    // fading out towards `outer_angle`. Angles are in radians.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        attenuation: Attenuation,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
 pub struct Light {
-    pub position: Vec3,
+    pub kind: LightKind,
     pub color: Vec3,
 }
 
 impl Light {
-    pub fn new(pos: Vec3, color: Vec3) -> Self {
+    pub fn directional(direction: Vec3, color: Vec3) -> Self {
+        Light {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            color,
+        }
+    }
+
+    pub fn point(pos: Vec3, color: Vec3, attenuation: Attenuation) -> Self {
         Light {
-            position: pos,
+            kind: LightKind::Point {
+                position: pos,
+                attenuation,
+            },
             color,
         }
     }
+
+    pub fn spot(pos: Vec3, direction: Vec3, angle: f32, color: Vec3) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                position: pos,
+                direction: direction.normalize(),
+                attenuation: Attenuation::default(),
+                inner_angle: angle * 0.8,
+                outer_angle: angle,
+            },
+            color,
+        }
+    }
+
+    // Where the light is, directional lights are everywhere
+    pub fn position(&self) -> Option<Vec3> {
+        match self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => Some(position),
+        }
+    }
+}
+
+// Pick the lights a frame is lit by when there are more than MAX_LIGHTS:
+// directional lights first, then the lights closest to `eye`
+pub fn closest_lights(mut lights: Vec<&Light>, eye: Vec3) -> Vec<&Light> {
+    if lights.len() > MAX_LIGHTS {
+        lights.sort_by(|a, b| {
+            let distance = |light: &Light| {
+                light
+                    .position()
+                    .map_or(0.0, |position| (position - eye).norm_squared())
+            };
+            distance(a).total_cmp(&distance(b))
+        });
+        lights.truncate(MAX_LIGHTS);
+    }
+    lights
+}
+
+// Fields of a light in the `lights` array of the fragment shader
+const LIGHT_FIELDS: [&str; 7] = [
+    "color",
+    "kind",
+    "position",
+    "direction",
+    "attenuation",
+    "inner_cutoff",
+    "outer_cutoff",
+];
+
+// Names like "lights[3].color" of every field of every light, built once
+// rather than for every draw
+static LIGHT_UNIFORM_NAMES: LazyLock<Vec<[String; LIGHT_FIELDS.len()]>> = LazyLock::new(|| {
+    (0..MAX_LIGHTS)
+        .map(|i| LIGHT_FIELDS.map(|field| format!("lights[{}].{}", i, field)))
+        .collect()
+});
+
+// Uniforms of a draw along with the `lights` array and `light_count` of the
+// fragment shader. Without lights only ambient light is left.
+pub struct LightUniforms<'l, U> {
+    pub lights: &'l [&'l Light],
+    pub uniforms: U,
+}
+
+impl<U: Uniforms> Uniforms for LightUniforms<'_, U> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut output: F) {
+        self.uniforms.visit_values(&mut output);
+
+        let lights = &self.lights[..self.lights.len().min(MAX_LIGHTS)];
+        output("light_count", UniformValue::SignedInt(lights.len() as i32));
+
+        for (light, names) in lights.iter().zip(LIGHT_UNIFORM_NAMES.iter()) {
+            // Values a kind of light doesn't use are ignored by the shader
+            let (kind, position, direction, attenuation, cutoff) = match light.kind {
+                LightKind::Directional { direction } => {
+                    (0, Vec3::zeros(), direction, Attenuation::NONE, (0.0, 0.0))
+                }
+                LightKind::Point {
+                    position,
+                    attenuation,
+                } => (1, position, Vec3::zeros(), attenuation, (0.0, 0.0)),
+                LightKind::Spot {
+                    position,
+                    direction,
+                    attenuation,
+                    inner_angle,
+                    outer_angle,
+                } => (
+                    2,
+                    position,
+                    direction,
+                    attenuation,
+                    (inner_angle.cos(), outer_angle.cos()),
+                ),
+            };
+
+            // In the order of `LIGHT_FIELDS`
+            let values = [
+                UniformValue::Vec3(light.color.into()),
+                UniformValue::SignedInt(kind),
+                UniformValue::Vec3(position.into()),
+                UniformValue::Vec3(direction.into()),
+                UniformValue::Vec3([
+                    attenuation.constant,
+                    attenuation.linear,
+                    attenuation.quadratic,
+                ]),
+                UniformValue::Float(cutoff.0),
+                UniformValue::Float(cutoff.1),
+            ];
+            for (name, value) in names.iter().zip(values) {
+                output(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glium::uniforms::EmptyUniforms;
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    fn point_at(x: f32) -> Light {
+        Light::point(vec3(x, 0.0, 0.0), Vec3::repeat(1.0), Attenuation::default())
+    }
+
+    #[test]
+    fn no_lights() {
+        assert!(closest_lights(vec![], Vec3::zeros()).is_empty());
+    }
+
+    #[test]
+    fn few_lights_are_kept_as_they_are() {
+        let far = point_at(100.0);
+        let near = point_at(1.0);
+        let lights = closest_lights(vec![&far, &near], Vec3::zeros());
+        assert!(std::ptr::eq(lights[0], &far));
+        assert!(std::ptr::eq(lights[1], &near));
+    }
+
+    #[test]
+    fn closest_lights_are_kept() {
+        // Further from the eye the later they come, with the sun last
+        let points: Vec<Light> = (0..MAX_LIGHTS + 4).map(|i| point_at(i as f32)).collect();
+        let sun = Light::directional(vec3(0.0, -1.0, 0.0), Vec3::repeat(1.0));
+        let mut lights: Vec<&Light> = points.iter().rev().collect();
+        lights.push(&sun);
+
+        let eye = vec3(-5.0, 0.0, 0.0);
+        let lights = closest_lights(lights, eye);
+        assert_eq!(lights.len(), MAX_LIGHTS);
+        assert!(std::ptr::eq(lights[0], &sun));
+        for (light, expected) in lights[1..].iter().zip(&points) {
+            assert!(std::ptr::eq(*light, expected));
+        }
+    }
+
+    #[test]
+    fn directional_lights_are_always_kept() {
+        let points: Vec<Light> = (0..MAX_LIGHTS).map(|i| point_at(i as f32)).collect();
+        let sun = Light::directional(vec3(0.0, -1.0, 0.0), Vec3::repeat(1.0));
+        let mut lights: Vec<&Light> = points.iter().collect();
+        lights.push(&sun);
+
+        // Even when every point light is right next to the eye
+        let lights = closest_lights(lights, Vec3::zeros());
+        assert_eq!(lights.len(), MAX_LIGHTS);
+        assert!(lights.iter().any(|light| std::ptr::eq(*light, &sun)));
+    }
+
+    #[test]
+    fn uniforms_name_every_field() {
+        let points: Vec<Light> = (0..MAX_LIGHTS + 1).map(|i| point_at(i as f32)).collect();
+        let lights: Vec<&Light> = points.iter().collect();
+        let uniforms = LightUniforms {
+            lights: &lights,
+            uniforms: EmptyUniforms,
+        };
+
+        let mut names = vec![];
+        uniforms.visit_values(|name, value| {
+            if name == "light_count" {
+                assert!(
+                    matches!(value, UniformValue::SignedInt(count) if count == MAX_LIGHTS as i32)
+                );
+            }
+            names.push(name.to_string());
+        });
+        // Lights past the maximum are left out
+        assert_eq!(names.len(), 1 + MAX_LIGHTS * LIGHT_FIELDS.len());
+        assert_eq!(names[1], "lights[0].color");
+        assert_eq!(
+            *names.last().unwrap(),
+            format!("lights[{}].outer_cutoff", MAX_LIGHTS - 1)
+        );
+    }
 }
@@ -1,32 +1,59 @@
 extern crate glium;
 
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
+use assets::Assets;
 use bounding_circle::BoundingCircle;
-use camera::{camera_system, Camera};
-use glium::glutin::event::{self, VirtualKeyCode};
-use glium::{glutin::event_loop::EventLoop, Display};
+use camera::{camera_key_system, camera_system, Camera, CameraRig, ProjectionKind};
+use control_group::{control_group_system, ControlGroups};
+use debug_draw::DebugDraw;
+use glium::glutin::{
+    dpi::PhysicalSize,
+    event::{self, VirtualKeyCode},
+};
+use glium::{backend::Facade, glutin::event_loop::EventLoop, Display};
+use hot_reload::HotReloader;
 use hecs::{Entity, World};
 use light::Light;
-use mesh_repo::MeshRepo;
+use mesh_data::MeshData;
 use mouse::{cursor_system, mouse_click_system, Cursor, Mouse, mouse_scroll_system};
 use movement::{movement_system, Movement};
-use nalgebra_glm::{vec3, Vec3};
-use render::render_system;
+use nalgebra_glm::{vec2, vec3, Vec3};
+use render::{render_scene, render_system, Shaders};
+use render_cache::RenderCache;
+use screenshot::{screenshot_path, Offscreen};
 use selectable::{select_system, Selectable};
 use transformation::Transformation;
 
+pub mod assets;
 pub mod bounding_circle;
 pub mod camera;
+pub mod control_group;
+pub mod debug_draw;
+pub mod gltf_loader;
+pub mod headless;
+pub mod hot_reload;
 pub mod light;
+pub mod material;
 pub mod math;
 pub mod mesh;
-pub mod mesh_repo;
+pub mod mesh_cache;
+pub mod mesh_data;
 pub mod mouse;
+pub mod normals;
 pub mod render;
+pub mod render_cache;
+pub mod renderer;
 pub mod movement;
+pub mod picking;
 pub mod selectable;
+pub mod screenshot;
 pub mod shader;
+pub mod shadow;
 pub mod transformation;
 pub mod vertex;
 pub mod wavefront;
@@ -43,6 +70,34 @@ fn rotate_system(world: &mut World) {
     }
 }
 
+// Draw the collision radius of every unit and an arrow to where it is moving
+fn debug_overlay_system(world: &World, debug_draw: &mut DebugDraw) {
+    for (_, (transformation, selectable, movement)) in world
+        .query::<(&Transformation, &Selectable, Option<&Movement>)>()
+        .iter()
+    {
+        let circle = selectable.bounding_circle;
+        let center = transformation.pos
+            + vec3(circle.ground_pos.x, 0.0, circle.ground_pos.y) * transformation.scale;
+        debug_draw.circle(center, circle.r * transformation.scale, debug_draw::YELLOW, Duration::ZERO);
+
+        if let Some(target) = movement.and_then(|movement| movement.target_pos) {
+            let target = vec3(target.x, 0.0, target.y);
+            debug_draw.arrow(transformation.pos, target, debug_draw::CYAN, Duration::ZERO);
+            debug_draw.cross(target, 0.5, debug_draw::CYAN, Duration::ZERO);
+        }
+    }
+}
+
+// Keep the camera's aspect ratio and cursor picking in step with the window size
+fn resize_system(world: &mut World, mouse: &mut Mouse, camera_entity: Entity, size: PhysicalSize<u32>) {
+    mouse.resize(size);
+    world
+        .get::<&mut Camera>(camera_entity)
+        .unwrap()
+        .resize(size.width, size.height);
+}
+
 fn initialize_glium(w: u32, h: u32) -> (Display, EventLoop<()>) {
     let event_loop = glium::glutin::event_loop::EventLoop::new();
     let wb = glium::glutin::window::WindowBuilder::new()
@@ -57,44 +112,75 @@ fn initialize_glium(w: u32, h: u32) -> (Display, EventLoop<()>) {
     (display, event_loop)
 }
 
-fn main() {
-    let (display, event_loop) = initialize_glium(WIDTH, HEIGHT);
-    let mut mouse = Mouse::new(WIDTH, HEIGHT);
-
-    // Create the world
-    let mut world = World::new();
+// Write the binary mesh cache of every given model, returns the exit code
+fn convert(paths: &[String]) -> i32 {
+    if paths.is_empty() {
+        eprintln!("Usage: topdown convert <model>...");
+        return 2;
+    }
 
-    // Set up mesh repository and load shaders
-    let mut mesh_repo = MeshRepo::new();
-    let shader = shader::load(&display, "fragment.glsl".into(), "vertex.glsl".into());
+    let mut code = 0;
+    for path in paths {
+        let path = PathBuf::from(path);
+        let cache = mesh_cache::cache_path(&path);
+        let result = MeshData::load_source(path.clone())
+            .and_then(|data| Ok(mesh_cache::save(&data, &cache)?));
+        match result {
+            Ok(()) => println!("{} -> {}", path.display(), cache.display()),
+            Err(err) => {
+                eprintln!("Failed to convert {}: {}", path.display(), err);
+                code = 1;
+            }
+        }
+    }
+    code
+}
 
+// Spawn the camera, lights, terrain and units, returns the camera and cursor entities.
+// The camera looks at a viewport of `width` by `height` pixels.
+fn load_scene(
+    facade: &impl Facade,
+    world: &mut World,
+    assets: &mut Assets,
+    width: u32,
+    height: u32,
+) -> (Entity, Entity) {
     // Floor
-    let mesh = mesh::Mesh::new_floor(&display, Vec3::new(0.2, 0.1, 0.1));
-    let floor_mesh = mesh_repo.insert(mesh);
-
-    // Load an example mesh and add to mesh repo (cube)
-    let mesh = mesh::Mesh::load(&display, "tank.obj".into(), Vec3::new(0.0, 0.3, 0.0));
-    let select_circle = BoundingCircle::from_mesh(&mesh);
-    let tank_mesh = mesh_repo.insert(mesh);
-
-    // Camera
-    let camera_entity = world.spawn((Camera::new(
-        Vec3::new(10.0, 30.0, 10.0), // eye
-        0.1,
-        50.0,
-    ),));
-
-    // Light
+    let floor_mesh = assets.insert_mesh(facade, None, MeshData::floor(), Vec3::new(1.0, 1.0, 1.0));
+
+    // Load an example mesh, falling back to a placeholder
+    let tank_color = Vec3::new(0.7, 0.9, 0.7);
+    let tank_mesh = assets
+        .load_mesh(facade, "tank.obj", tank_color)
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to load tank.obj: {}", err);
+            assets.insert_mesh(facade, Some("tank.obj".into()), MeshData::placeholder(), tank_color)
+        });
+    let select_circle = BoundingCircle::from_mesh(&assets.mesh(&tank_mesh).data);
+
+    // Camera, looking at the middle of the floor and kept above it
+    let rig = CameraRig::new(
+        Vec3::zeros(),               // Focus
+        std::f32::consts::FRAC_PI_4, // Yaw
+        33.0,                        // Distance
+        vec2(-10.0, -10.0),
+        vec2(10.0, 10.0),
+    );
+    let mut camera = Camera::new(0.1, 50.0, width, height);
+    rig.apply(&mut camera);
+    let camera_entity = world.spawn((camera, rig));
+
+    // Sun
     world.spawn((
-        Light::new(
-            Vec3::new(-5.0, 5.0, 5.0), // Pos
-            Vec3::new(1.0, 1.0, 1.0),  // Color
+        Light::directional(
+            Vec3::new(5.0, -5.0, -5.0), // Direction
+            Vec3::new(1.0, 1.0, 1.0),   // Color
         ),
     ));
 
     // Terrain
     world.spawn((
-        floor_mesh.clone(),
+        floor_mesh,
         Transformation::new(vec3(0.0, 0.0, 0.0), 0.0, 1.0),
     ));
 
@@ -111,23 +197,162 @@ fn main() {
     // Spawn a mouse cursor
     let cursor_entity = world.spawn((Cursor {
         position: vec3(0.5, 0.0, 0.5),
+        ray: None,
     },));
 
+    (camera_entity, cursor_entity)
+}
+
+// Value following a command line flag, like "out.png" in "--screenshot out.png"
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.get(i + 1).map(String::as_str)
+}
+
+// Render `frames` frames of the scene without a window and save the last one
+// as PNG, returns the exit code
+fn screenshot(args: &[String]) -> i32 {
+    let usage =
+        "Usage: topdown --screenshot <out.png> [--frames N] [--size WIDTHxHEIGHT] [--ortho]";
+    let (Some(path), Some(frames), Some((width, height))) = (
+        flag_value(args, "--screenshot"),
+        flag_value(args, "--frames").map_or(Some(1), |n| n.parse::<u32>().ok()),
+        flag_value(args, "--size").map_or(Some((WIDTH, HEIGHT)), |size| {
+            let (width, height) = size.split_once('x')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        }),
+    ) else {
+        eprintln!("{}", usage);
+        return 2;
+    };
+
+    let context = match headless::create_context() {
+        Ok(context) => context,
+        Err(err) => {
+            eprintln!("Failed to create a headless OpenGL context: {}", err);
+            return 1;
+        }
+    };
+
+    let mut world = World::new();
+    let mut assets = Assets::new(&context);
+    let shaders = match Shaders::load(&context, &mut assets) {
+        Ok(shaders) => shaders,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let mut render_cache = RenderCache::new(&context);
+    let mut debug_draw = DebugDraw::default();
+    let (camera_entity, _) = load_scene(&context, &mut world, &mut assets, width, height);
+    if args.iter().any(|arg| arg == "--ortho") {
+        let mut camera = world.get::<&mut Camera>(camera_entity).unwrap();
+        camera.set_kind(ProjectionKind::Orthographic);
+    }
+
+    let result = Offscreen::new(&context, width, height).and_then(|offscreen| {
+        for _ in 0..frames.max(1) {
+            rotate_system(&mut world);
+            render_scene(
+                &context,
+                &mut offscreen.framebuffer(&context)?,
+                &assets,
+                &mut render_cache,
+                &world,
+                &debug_draw,
+                &shaders,
+                camera_entity,
+            );
+            debug_draw.end_frame();
+            movement_system(&mut world);
+        }
+        Ok(offscreen.read().save(path)?)
+    });
+    match result {
+        Ok(()) => {
+            println!("Saved {}", path);
+            0
+        }
+        Err(err) => {
+            eprintln!("Failed to take screenshot: {}", err);
+            1
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("convert") {
+        std::process::exit(convert(&args[2..]));
+    }
+    if args.iter().any(|arg| arg == "--screenshot") {
+        std::process::exit(screenshot(&args));
+    }
+
+    let (display, event_loop) = initialize_glium(WIDTH, HEIGHT);
+    // The window is WIDTH by HEIGHT logical pixels, mouse events and the
+    // framebuffer use physical pixels
+    let window_size = display.gl_window().window().inner_size();
+    let mut mouse = Mouse::new(window_size);
+
+    // Create the world
+    let mut world = World::new();
+
+    // Set up the asset manager and load shaders
+    let mut assets = Assets::new(&display);
+    let shaders = Shaders::load(&display, &mut assets).unwrap_or_else(|err| panic!("{}", err));
+
+    // GPU resources shared by every frame
+    let mut render_cache = RenderCache::new(&display);
+
+    // Debug lines queued by systems, drawn with the next frame
+    let mut debug_draw = DebugDraw::default();
+
+    let (camera_entity, cursor_entity) = load_scene(
+        &display,
+        &mut world,
+        &mut assets,
+        window_size.width,
+        window_size.height,
+    );
+
     // Selected entities
     let mut selected: HashSet<Entity> = HashSet::new();
+    let mut control_groups = ControlGroups::default();
     let mut pressed_keys: HashSet<VirtualKeyCode> = HashSet::new();
     let mut modifiers = Default::default();
 
+    // Draw collision radii and movement targets
+    let mut show_debug = false;
+
+    // Reload shaders, meshes and textures when their files change
+    let mut hot_reloader = HotReloader::default();
+
+    let mut last_frame = Instant::now();
+
     event_loop.run(move |event, _, control_flow| match event {
         event::Event::WindowEvent { event, .. } => match event {
             event::WindowEvent::CloseRequested => {
                 *control_flow = glium::glutin::event_loop::ControlFlow::Exit;
-                return;
+            }
+            event::WindowEvent::Resized(size) => {
+                resize_system(&mut world, &mut mouse, camera_entity, size);
+            }
+            event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                resize_system(&mut world, &mut mouse, camera_entity, *new_inner_size);
             }
             event::WindowEvent::CursorMoved { position, .. } => {
                 mouse.update(&position);
             }
+            event::WindowEvent::CursorEntered { .. } => {
+                mouse.in_window = true;
+            }
+            event::WindowEvent::CursorLeft { .. } => {
+                mouse.in_window = false;
+            }
             event::WindowEvent::MouseInput { state, button, .. } => {
+                mouse.update_button(button, state);
                 mouse_click_system(&mut world, &mut selected, button, state, modifiers, cursor_entity);
             }
             event::WindowEvent::MouseWheel { delta, ..} => {
@@ -139,7 +364,62 @@ fn main() {
             event::WindowEvent::KeyboardInput { input, .. } => match input.state {
                 event::ElementState::Pressed => {
                     if let Some(keycode) = input.virtual_keycode {
-                        pressed_keys.insert(keycode);
+                        // Held keys repeat, only the first press counts for
+                        // bookmarks, following and control groups
+                        if pressed_keys.insert(keycode) {
+                            camera_key_system(&mut world, keycode, modifiers, &selected, camera_entity);
+                            control_group_system(
+                                &mut world,
+                                &mut control_groups,
+                                &mut selected,
+                                keycode,
+                                modifiers,
+                                camera_entity,
+                            );
+                        }
+
+                        // Switch between perspective and orthographic projection
+                        if keycode == VirtualKeyCode::F5 {
+                            world.get::<&mut Camera>(camera_entity).unwrap().toggle_kind();
+                        }
+
+                        // Toggle shadows
+                        if keycode == VirtualKeyCode::F6 {
+                            let settings = &mut render_cache.shadow_map.settings;
+                            settings.enabled = !settings.enabled;
+                        }
+
+                        // Toggle the debug overlay
+                        if keycode == VirtualKeyCode::F7 {
+                            show_debug = !show_debug;
+                        }
+
+                        // Drop meshes, textures and shaders nothing uses anymore
+                        if keycode == VirtualKeyCode::F8 {
+                            println!("Unloaded {} unused assets", assets.unload_unused());
+                        }
+
+                        // Save what the window shows as PNG
+                        if keycode == VirtualKeyCode::F12 {
+                            let (width, height) = display.get_framebuffer_dimensions();
+                            let path = screenshot_path();
+                            let result = screenshot::capture(&display, width, height, &path, |target| {
+                                render_scene(
+                                    &display,
+                                    target,
+                                    &assets,
+                                    &mut render_cache,
+                                    &world,
+                                    &debug_draw,
+                                    &shaders,
+                                    camera_entity,
+                                )
+                            });
+                            match result {
+                                Ok(()) => println!("Saved {}", path.display()),
+                                Err(err) => eprintln!("Failed to take screenshot: {}", err),
+                            }
+                        }
                     }
                 }
                 event::ElementState::Released => {
@@ -151,11 +431,28 @@ fn main() {
             _ => {}
         },
         event::Event::MainEventsCleared => {
+            // Time since the last frame, limited so a stall doesn't make a jump
+            let now = Instant::now();
+            let dt = (now - last_frame).as_secs_f32().min(0.1);
+            last_frame = now;
+
+            hot_reloader.update(&display, &mut assets);
             rotate_system(&mut world);
-            render_system(&display, &mut mesh_repo, &world, &shader, camera_entity);
+            if show_debug {
+                debug_overlay_system(&world, &mut debug_draw);
+            }
+            render_system(
+                &display,
+                &assets,
+                &mut render_cache,
+                &world,
+                &mut debug_draw,
+                &shaders,
+                camera_entity,
+            );
             cursor_system(&mouse, &mut world, cursor_entity, camera_entity);
-            select_system(&mut world, cursor_entity);
-            camera_system(&mut world, &pressed_keys, camera_entity);
+            select_system(&mut world, &assets, cursor_entity);
+            camera_system(&mut world, &pressed_keys, &mut mouse, dt, camera_entity);
             movement_system(&mut world);
         }
         _ => {}
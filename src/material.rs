@@ -0,0 +1,156 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use image::RgbaImage;
+use nalgebra_glm::Vec3;
+
+use crate::wavefront::{ObjError, Tokens};
+
+// Where the pixels of a texture come from
+#[derive(Clone)]
+pub enum TextureSource {
+    File(PathBuf),
+    // Already decoded image, for textures embedded in the model file
+    Image(Rc<RgbaImage>),
+}
+
+impl fmt::Debug for TextureSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            TextureSource::Image(image) => {
+                write!(f, "Image({}x{})", image.width(), image.height())
+            }
+        }
+    }
+}
+
+// Surface parameters of a single `newmtl` entry in a .mtl file
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub shininess: f32,
+    pub emissive: Vec3,
+    pub dissolve: f32,
+    pub illum: u32,
+    pub diffuse_map: Option<TextureSource>,
+    pub bump_map: Option<TextureSource>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::new(),
+            ambient: Vec3::new(1.0, 1.0, 1.0),
+            diffuse: Vec3::new(1.0, 1.0, 1.0),
+            specular: Vec3::new(0.0, 0.0, 0.0),
+            shininess: 32.0,
+            emissive: Vec3::new(0.0, 0.0, 0.0),
+            dissolve: 1.0,
+            illum: 1,
+            diffuse_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+fn color(tokens: &mut Tokens) -> Result<Vec3, ObjError> {
+    let r: f32 = tokens.number()?;
+    // A single value is a grey level
+    let g: f32 = tokens.optional_number()?.unwrap_or(r);
+    let b: f32 = tokens.optional_number()?.unwrap_or(g);
+    Ok(Vec3::new(r, g, b))
+}
+
+// Texture statements may carry options such as `-bm 0.5` before the file name,
+// so the file name is taken to be the last argument. It is relative to the .mtl file.
+fn texture_path(tokens: &mut Tokens, dir: &Path) -> Result<TextureSource, ObjError> {
+    let (_, mut file_name) = tokens.argument()?;
+    while let Some((_, token)) = tokens.optional_argument() {
+        file_name = token;
+    }
+    Ok(TextureSource::File(dir.join(file_name)))
+}
+
+pub fn load(path: &Path) -> Result<Vec<Material>, ObjError> {
+    let stream = BufReader::new(File::open(path)?);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut materials: Vec<Material> = vec![];
+
+    for (line_idx, line) in stream.lines().enumerate() {
+        let line = line?;
+        let mut tokens = Tokens::new(&line, line_idx + 1);
+
+        // Skip empty lines and comments
+        let command = match tokens.command() {
+            Some(command) if !command.starts_with('#') => command,
+            _ => continue,
+        };
+
+        if command == "newmtl" {
+            let (_, name) = tokens.argument()?;
+            materials.push(Material {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        // Statements before the first `newmtl` don't belong to any material
+        let Some(material) = materials.last_mut() else {
+            continue;
+        };
+
+        match command {
+            "Ka" => material.ambient = color(&mut tokens)?,
+            "Kd" => material.diffuse = color(&mut tokens)?,
+            "Ks" => material.specular = color(&mut tokens)?,
+            "Ke" => material.emissive = color(&mut tokens)?,
+            "Ns" => material.shininess = tokens.number()?,
+            "d" => material.dissolve = tokens.number()?,
+            "Tr" => material.dissolve = 1.0 - tokens.number::<f32>()?,
+            "illum" => material.illum = tokens.number()?,
+            "map_Kd" => material.diffuse_map = Some(texture_path(&mut tokens, dir)?),
+            "map_Bump" | "map_bump" | "bump" => {
+                material.bump_map = Some(texture_path(&mut tokens, dir)?)
+            }
+            // Statements the renderer has no use for, like `Ni` or `map_Ks`,
+            // and extensions of other tools
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn unknown_statements_are_skipped() {
+        let path = std::env::temp_dir().join("topdown-unknown-statements.mtl");
+        fs::write(
+            &path,
+            "newmtl Metal\nNs 250\nPbrExtension 1 2\nKd 0.5\nvendor_thing\n",
+        )
+        .unwrap();
+        let materials = load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let materials = materials.unwrap();
+        assert_eq!(materials.len(), 1);
+        assert_eq!(materials[0].shininess, 250.0);
+        assert_eq!(materials[0].diffuse, Vec3::new(0.5, 0.5, 0.5));
+    }
+}
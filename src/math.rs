@@ -1 +1,192 @@
+use nalgebra_glm::{Mat4, Vec3};
 
+// Half line from `origin` in `direction`. Intersections return the distance
+// `t` along the ray, the hit point is `at(t)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    // Ray with a normalized direction, so distances are in world units
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // The same ray in another space, like the model space of a mesh. The
+    // direction is not normalized again, so distances along the transformed
+    // ray are the same as along this one.
+    pub fn transform(&self, matrix: &Mat4) -> Ray {
+        Ray {
+            origin: (matrix * self.origin.push(1.0)).xyz(),
+            direction: (matrix * self.direction.push(0.0)).xyz(),
+        }
+    }
+
+    // Plane through `point` facing either way along `normal`
+    pub fn intersect_plane(&self, point: Vec3, normal: Vec3) -> Option<f32> {
+        let facing = self.direction.dot(&normal);
+        if facing.abs() <= f32::EPSILON {
+            return None;
+        }
+        let t = (point - self.origin).dot(&normal) / facing;
+        (t >= 0.0).then_some(t)
+    }
+
+    // Flat circle parallel to the ground, like a `BoundingCircle`
+    pub fn intersect_circle(&self, center: Vec3, radius: f32) -> Option<f32> {
+        let t = self.intersect_plane(center, Vec3::y())?;
+        ((self.at(t) - center).norm() <= radius).then_some(t)
+    }
+
+    // Axis aligned box, hit from outside or inside (slab test)
+    pub fn intersect_aabb(&self, min: Vec3, max: Vec3) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::MAX;
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let t1 = (min[axis] - self.origin[axis]) * inverse;
+            let t2 = (max[axis] - self.origin[axis]) * inverse;
+            // Rays parallel to a slab give NaN when they start on its edge
+            if t1.is_nan() || t2.is_nan() {
+                continue;
+            }
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        (near <= far).then_some(near)
+    }
+
+    // Triangle seen from either side (Möller-Trumbore)
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let s = self.origin - a;
+        let u = s.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = self.direction.dot(&q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&q) * inverse;
+        (t >= 0.0).then_some(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    fn assert_hit(hit: Option<f32>, expected: f32) {
+        match hit {
+            Some(t) => assert!(
+                (t - expected).abs() < 1e-5,
+                "hit at {}, not {}",
+                t,
+                expected
+            ),
+            None => panic!("missed, expected a hit at {}", expected),
+        }
+    }
+
+    // Straight down onto the ground from 10 units above `x`, `z`
+    fn down(x: f32, z: f32) -> Ray {
+        Ray::new(vec3(x, 10.0, z), vec3(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn plane() {
+        assert_hit(
+            down(3.0, 4.0).intersect_plane(Vec3::zeros(), Vec3::y()),
+            10.0,
+        );
+        // Seen from below
+        assert_hit(
+            down(0.0, 0.0).intersect_plane(Vec3::zeros(), -Vec3::y()),
+            10.0,
+        );
+        // Behind the ray
+        let up = Ray::new(vec3(0.0, 10.0, 0.0), Vec3::y());
+        assert_eq!(up.intersect_plane(Vec3::zeros(), Vec3::y()), None);
+        // Parallel to the plane
+        let level = Ray::new(vec3(0.0, 10.0, 0.0), Vec3::x());
+        assert_eq!(level.intersect_plane(Vec3::zeros(), Vec3::y()), None);
+    }
+
+    #[test]
+    fn circle() {
+        let center = vec3(1.0, 0.0, 1.0);
+        assert_hit(down(1.5, 1.5).intersect_circle(center, 1.0), 10.0);
+        assert_eq!(down(2.5, 1.0).intersect_circle(center, 1.0), None);
+    }
+
+    #[test]
+    fn aabb() {
+        let min = Vec3::repeat(-1.0);
+        let max = Vec3::repeat(1.0);
+        assert_hit(down(0.5, -0.5).intersect_aabb(min, max), 9.0);
+        assert_eq!(down(1.5, 0.0).intersect_aabb(min, max), None);
+        // From inside the box
+        assert_hit(
+            Ray::new(Vec3::zeros(), Vec3::y()).intersect_aabb(min, max),
+            0.0,
+        );
+        // Passing beside a corner
+        let diagonal = Ray::new(vec3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 1.0));
+        assert_eq!(diagonal.intersect_aabb(min, max), None);
+    }
+
+    #[test]
+    fn aabb_parallel_to_an_axis() {
+        let min = Vec3::repeat(-1.0);
+        let max = Vec3::repeat(1.0);
+        // Along z on the top face of the box, the y slab gives NaN and is skipped
+        let on_edge = Ray::new(vec3(0.0, 1.0, -5.0), Vec3::z());
+        assert_hit(on_edge.intersect_aabb(min, max), 4.0);
+        // Along z inside the y slab
+        let inside = Ray::new(vec3(0.0, 0.5, -5.0), Vec3::z());
+        assert_hit(inside.intersect_aabb(min, max), 4.0);
+        // Along z above the box
+        let above = Ray::new(vec3(0.0, 2.0, -5.0), Vec3::z());
+        assert_eq!(above.intersect_aabb(min, max), None);
+    }
+
+    #[test]
+    fn triangle() {
+        let a = vec3(0.0, 0.0, 0.0);
+        let b = vec3(2.0, 0.0, 0.0);
+        let c = vec3(0.0, 0.0, 2.0);
+        assert_hit(down(0.5, 0.5).intersect_triangle(a, b, c), 10.0);
+        // Seen from the back
+        assert_hit(down(0.5, 0.5).intersect_triangle(a, c, b), 10.0);
+        // Outside the hypotenuse and outside an edge
+        assert_eq!(down(1.5, 1.5).intersect_triangle(a, b, c), None);
+        assert_eq!(down(-0.5, 0.5).intersect_triangle(a, b, c), None);
+        // Behind the ray
+        let up = Ray::new(vec3(0.5, 10.0, 0.5), Vec3::y());
+        assert_eq!(up.intersect_triangle(a, b, c), None);
+        // In the triangle's plane
+        let level = Ray::new(vec3(-1.0, 0.0, 0.5), Vec3::x());
+        assert_eq!(level.intersect_triangle(a, b, c), None);
+    }
+}
@@ -1,76 +1,300 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, ops::Range};
 
-use glium::{texture::SrgbTexture2d, Display, VertexBuffer};
-use nalgebra_glm::Vec3;
+use glium::{
+    backend::Facade,
+    index::{IndicesSource, PrimitiveType},
+    IndexBuffer, VertexBuffer,
+};
+use nalgebra_glm::{Mat4, Vec3};
 
 use crate::{
-    texture::{load_from, sample_texture},
+    assets::TextureId,
+    material::Material,
+    mesh_data::{Bounds, MeshData},
     vertex::Vertex,
-    wavefront,
 };
 
+// Textures a material is drawn with, the defaults of `Assets` stand in for the
+// maps a material doesn't have
+#[derive(Clone, Debug)]
+pub struct MaterialTextures {
+    pub diffuse: TextureId,
+    // Tangent space normals, stored linear
+    pub normal: TextureId,
+}
+
+// Range of a mesh's index buffer drawn with one material
+pub struct SubMesh {
+    pub range: Range<usize>,
+    pub material: Material,
+    pub textures: MaterialTextures,
+}
+
+// Named piece of a mesh, like the turret of a tank, that can be posed on its own
+pub struct MeshPart {
+    pub name: String,
+    // Index of the part this one is attached to, it moves along with its parent
+    pub parent: Option<usize>,
+    // Center of the part's bounding box in model space, to rotate the part around
+    pub center: Vec3,
+    pub submeshes: Vec<SubMesh>,
+}
+
+impl MeshPart {
+    fn new(
+        name: String,
+        parent: Option<usize>,
+        submeshes: Vec<SubMesh>,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Self {
+        // Parts without geometry, like grouping nodes of a glTF scene, rotate around the origin
+        let bounds = Bounds::from_vertices(submeshes.iter().flat_map(|submesh| {
+            indices[submesh.range.clone()]
+                .iter()
+                .map(|&i| &vertices[i as usize])
+        }));
+
+        MeshPart {
+            name,
+            parent,
+            center: bounds.center(),
+            submeshes,
+        }
+    }
+}
+
+// Per-entity transforms of the parts of its mesh, applied in model space before
+// the entity's `Transformation`. Parts without a transform are left in place.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct PartPose {
+    transforms: HashMap<String, Mat4>,
+}
+
+impl PartPose {
+    pub fn set(&mut self, part: &str, transform: Mat4) {
+        self.transforms.insert(part.to_string(), transform);
+    }
+
+    pub fn get(&self, part: &str) -> Mat4 {
+        self.transforms
+            .get(part)
+            .copied()
+            .unwrap_or_else(Mat4::identity)
+    }
+
+    // Rotate a part around the vertical axis through its center
+    pub fn set_rotation(&mut self, part: &MeshPart, angle: f32) {
+        let transform = Mat4::new_translation(&part.center)
+            * Mat4::new_rotation(Vec3::y() * angle)
+            * Mat4::new_translation(&-part.center);
+        self.set(&part.name, transform);
+    }
+}
+
+// Index buffer using the smallest index type that fits the mesh
+pub enum MeshIndices {
+    U16(IndexBuffer<u16>),
+    U32(IndexBuffer<u32>),
+}
+
+impl MeshIndices {
+    pub fn new(display: &impl Facade, indices: &[u32]) -> Self {
+        let primitive = PrimitiveType::TrianglesList;
+        if indices.iter().all(|&i| i <= u16::MAX as u32) {
+            let indices: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+            MeshIndices::U16(
+                IndexBuffer::new(display, primitive, &indices)
+                    .expect("Failed to create index buffer"),
+            )
+        } else {
+            MeshIndices::U32(
+                IndexBuffer::new(display, primitive, indices)
+                    .expect("Failed to create index buffer"),
+            )
+        }
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> IndicesSource<'_> {
+        match self {
+            MeshIndices::U16(buffer) => buffer.slice(range).map(IndicesSource::from),
+            MeshIndices::U32(buffer) => buffer.slice(range).map(IndicesSource::from),
+        }
+        .expect("Submesh range out of bounds")
+    }
+}
+
 pub struct Mesh {
     pub vertex_buffer: VertexBuffer<Vertex>,
+    pub index_buffer: MeshIndices,
     pub color: Vec3,
-    pub vertices: Vec<Vertex>,
-    pub texture: SrgbTexture2d,
+    // CPU side copy of the geometry, for bounds and picking
+    pub data: MeshData,
+    pub parts: Vec<MeshPart>,
 }
 
 impl Mesh {
-    pub fn load(display: &Display, path: PathBuf, color: Vec3) -> Self {
-        let (v_data, _) = wavefront::load(path);
-        let v_buffer = VertexBuffer::new(display, &v_data).expect("Failed to create vertex buffer");
+    // Create the GPU buffers for mesh data. `textures` holds the textures of each
+    // material, `default_textures` are used for faces without a material.
+    pub fn upload(
+        display: &impl Facade,
+        data: MeshData,
+        color: Vec3,
+        textures: Vec<MaterialTextures>,
+        default_textures: MaterialTextures,
+    ) -> Self {
+        let v_buffer =
+            VertexBuffer::new(display, &data.vertices).expect("Failed to create vertex buffer");
+        let i_buffer = MeshIndices::new(display, &data.indices);
+
+        let parts = data
+            .parts
+            .iter()
+            .map(|part| {
+                let submeshes = part
+                    .submeshes
+                    .iter()
+                    .map(|submesh| match submesh.material {
+                        Some(i) => SubMesh {
+                            range: submesh.range.clone(),
+                            material: data.materials[i].clone(),
+                            textures: textures[i].clone(),
+                        },
+                        None => SubMesh {
+                            range: submesh.range.clone(),
+                            material: Material::default(),
+                            textures: default_textures.clone(),
+                        },
+                    })
+                    .collect();
+                MeshPart::new(
+                    part.name.clone(),
+                    part.parent,
+                    submeshes,
+                    &data.vertices,
+                    &data.indices,
+                )
+            })
+            .collect();
 
-        let texture = SrgbTexture2d::new(display, load_from("tank_texture.png".into())).unwrap();
-        println!("{:?}, {:?}", texture.width(), texture.height());
         Mesh {
             vertex_buffer: v_buffer,
+            index_buffer: i_buffer,
             color,
-            vertices: v_data,
-            texture,
+            data,
+            parts,
         }
     }
 
-    pub fn new_floor(display: &Display, color: Vec3) -> Self {
-        let v_data = vec![
-            Vertex {
-                position: [10.0, 0.0, -10.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [1.0, 0.0],
-            },
-            Vertex {
-                position: [10.0, 0.0, 10.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [1.0, 1.0],
-            },
-            Vertex {
-                position: [-10.0, 0.0, -10.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 0.0],
-            },
-            Vertex {
-                position: [-10.0, 0.0, -10.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 0.0],
-            },
-            Vertex {
-                position: [10.0, 0.0, 10.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [1.0, 1.0],
-            },
-            Vertex {
-                position: [-10.0, 0.0, 10.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 1.0],
-            },
-        ];
+    pub fn part(&self, name: &str) -> Option<&MeshPart> {
+        self.parts.iter().find(|part| part.name == name)
+    }
 
-        let v_buffer = VertexBuffer::new(display, &v_data).expect("Failed to create vertex buffer");
-        Mesh {
-            vertex_buffer: v_buffer,
-            color,
-            vertices: v_data,
-            texture: SrgbTexture2d::new(display, sample_texture()).unwrap(),
+    // Model matrix of every part of an entity placed by `model`. Parts move
+    // along with their parent, which always comes first.
+    pub fn part_models(&self, model: Mat4, pose: Option<&PartPose>) -> Vec<Mat4> {
+        let mut models: Vec<Mat4> = Vec::with_capacity(self.parts.len());
+        for part in &self.parts {
+            let parent_model = match part.parent {
+                Some(parent) => models[parent],
+                None => model,
+            };
+            models.push(match pose {
+                Some(pose) => parent_model * pose.get(&part.name),
+                None => parent_model,
+            });
         }
+        models
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use nalgebra_glm::vec3;
+
+    use super::*;
+    use crate::{
+        assets::Handle,
+        headless,
+        mesh_data::{PartData, SubMeshData},
+    };
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            position: [x, y, z],
+            normal: [0.0, 1.0, 0.0],
+            texture_coord: [0.0, 0.0],
+        }
+    }
+
+    // A hull at the origin with a turret around (2, 1, 0) on top of it
+    fn tank() -> MeshData {
+        let vertices = vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(0.0, 0.0, 1.0),
+            vertex(1.0, 1.0, -1.0),
+            vertex(3.0, 1.0, -1.0),
+            vertex(1.0, 1.0, 1.0),
+        ];
+        let part = |name: &str, parent, range| PartData {
+            name: name.to_string(),
+            parent,
+            submeshes: vec![SubMeshData {
+                material: None,
+                range,
+            }],
+        };
+        MeshData {
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices: vec![0, 1, 2, 3, 4, 5],
+            materials: vec![],
+            parts: vec![part("Hull", None, 0..3), part("Turret", Some(0), 3..6)],
+            dependencies: vec![],
+        }
+    }
+
+    fn assert_point(model: &Mat4, point: Vec3, expected: Vec3) {
+        let moved = model.transform_point(&point.into()).coords;
+        assert!(
+            (moved - expected).norm() < 1e-5,
+            "{} != {}",
+            moved,
+            expected
+        );
+    }
+
+    #[test]
+    fn parts_rotate_around_their_center() {
+        let context = headless::create_context().unwrap();
+        let textures = MaterialTextures {
+            diffuse: Handle::detached(0),
+            normal: Handle::detached(1),
+        };
+        let mesh = Mesh::upload(&context, tank(), Vec3::zeros(), vec![], textures);
+        let turret = mesh.part("Turret").unwrap();
+        assert_eq!(turret.center, vec3(2.0, 1.0, 0.0));
+        assert!(mesh.part("Gun").is_none());
+
+        let mut pose = PartPose::default();
+        pose.set_rotation(turret, FRAC_PI_2);
+        let placed = Mat4::new_translation(&vec3(10.0, 0.0, 0.0));
+        let models = mesh.part_models(placed, Some(&pose));
+
+        // The hull is only placed, the turret turns a quarter around its center
+        assert_point(&models[0], Vec3::zeros(), vec3(10.0, 0.0, 0.0));
+        assert_point(&models[1], vec3(2.0, 1.0, 0.0), vec3(12.0, 1.0, 0.0));
+        assert_point(&models[1], vec3(3.0, 1.0, 0.0), vec3(12.0, 1.0, -1.0));
+
+        // Turning the hull takes the turret along
+        let hull = mesh.part("Hull").unwrap();
+        pose.set_rotation(hull, FRAC_PI_2);
+        let models = mesh.part_models(Mat4::identity(), Some(&pose));
+        assert_point(&models[1], turret.center, vec3(0.0, 1.0, -1.0));
+        assert_point(&models[0], vec3(1.0, 0.0, 0.5), vec3(0.5, 0.0, 0.0));
+        assert_eq!(models[1], models[0] * pose.get("Turret"));
     }
 }
@@ -0,0 +1,608 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::UNIX_EPOCH,
+};
+
+use image::RgbaImage;
+use nalgebra_glm::Vec3;
+
+use crate::{
+    material::{Material, TextureSource},
+    mesh_data::{Bounds, MeshData, PartData, SubMeshData},
+    vertex::Vertex,
+};
+
+// Binary mesh cache, written next to the model it was converted from.
+//
+// All numbers are little endian. The file starts with `MAGIC` and `VERSION`,
+// followed by the files the model depends on with their modification times,
+// the bounds, the vertices, the indices, the materials and the parts of the
+// model. Lists are prefixed with their length as a u32, strings are
+// UTF-8 bytes prefixed with their length. Bump `VERSION` whenever the layout
+// changes, older caches are then ignored and the source is parsed instead.
+const MAGIC: &[u8; 4] = b"TDMC";
+const VERSION: u32 = 2;
+
+// Marks a missing index, like a part without parent
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    NotACache,
+    UnsupportedVersion(u32),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Io(err) => write!(f, "failed to access mesh cache: {}", err),
+            CacheError::NotACache => write!(f, "not a mesh cache file"),
+            CacheError::UnsupportedVersion(version) => write!(
+                f,
+                "mesh cache version {} is not supported, expected {}",
+                version, VERSION
+            ),
+            CacheError::Corrupt(what) => write!(f, "mesh cache is corrupt: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CacheError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> Self {
+        CacheError::Io(err)
+    }
+}
+
+// Cache file belonging to a model file, "tank.obj" is cached in "tank.obj.mesh"
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut path = source.as_os_str().to_owned();
+    path.push(".mesh");
+    PathBuf::from(path)
+}
+
+// Modification time of a file in nanoseconds since the Unix epoch, 0 for a
+// missing file
+fn modification_time(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+// Whether the cache of a model exists, was written after the model was last
+// changed and none of the files the model depends on, like its material
+// libraries, changed since the cache was written
+pub fn is_fresh(source: &Path) -> bool {
+    let cache = cache_path(source);
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(&cache), modified(source)) {
+        (Ok(cache), Ok(source)) if cache >= source => {}
+        _ => return false,
+    }
+
+    let Ok(file) = File::open(&cache) else {
+        return false;
+    };
+    match Reader(BufReader::new(file)).header() {
+        Ok(dependencies) => dependencies
+            .iter()
+            .all(|(path, time)| modification_time(path) == *time),
+        Err(_) => false,
+    }
+}
+
+struct Writer<W: Write>(W);
+
+impl<W: Write> Writer<W> {
+    fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.0.write_all(&[value])
+    }
+
+    fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn f32(&mut self, value: f32) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn f32s(&mut self, values: &[f32]) -> io::Result<()> {
+        values.iter().try_for_each(|&value| self.f32(value))
+    }
+
+    fn vec3(&mut self, value: &Vec3) -> io::Result<()> {
+        self.f32s(value.as_slice())
+    }
+
+    fn len(&mut self, len: usize) -> io::Result<()> {
+        self.u32(len as u32)
+    }
+
+    fn index(&mut self, index: Option<usize>) -> io::Result<()> {
+        self.u32(index.map_or(NONE, |i| i as u32))
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.len(bytes.len())?;
+        self.0.write_all(bytes)
+    }
+
+    fn string(&mut self, value: &str) -> io::Result<()> {
+        self.bytes(value.as_bytes())
+    }
+
+    fn texture(&mut self, texture: &Option<TextureSource>) -> io::Result<()> {
+        match texture {
+            None => self.u8(0),
+            Some(TextureSource::File(path)) => {
+                self.u8(1)?;
+                self.string(&path.to_string_lossy())
+            }
+            Some(TextureSource::Image(image)) => {
+                self.u8(2)?;
+                self.u32(image.width())?;
+                self.u32(image.height())?;
+                self.bytes(image.as_raw())
+            }
+        }
+    }
+
+    fn material(&mut self, material: &Material) -> io::Result<()> {
+        self.string(&material.name)?;
+        self.vec3(&material.ambient)?;
+        self.vec3(&material.diffuse)?;
+        self.vec3(&material.specular)?;
+        self.f32(material.shininess)?;
+        self.vec3(&material.emissive)?;
+        self.f32(material.dissolve)?;
+        self.u32(material.illum)?;
+        self.texture(&material.diffuse_map)?;
+        self.texture(&material.bump_map)
+    }
+
+    fn mesh_data(&mut self, data: &MeshData) -> io::Result<()> {
+        self.0.write_all(MAGIC)?;
+        self.u32(VERSION)?;
+
+        self.len(data.dependencies.len())?;
+        for path in &data.dependencies {
+            self.string(&path.to_string_lossy())?;
+            self.u64(modification_time(path))?;
+        }
+
+        self.vec3(&data.bounds.min)?;
+        self.vec3(&data.bounds.max)?;
+
+        self.len(data.vertices.len())?;
+        for v in &data.vertices {
+            self.f32s(&v.position)?;
+            self.f32s(&v.normal)?;
+            self.f32s(&v.texture_coord)?;
+        }
+
+        self.len(data.indices.len())?;
+        for &i in &data.indices {
+            self.u32(i)?;
+        }
+
+        self.len(data.materials.len())?;
+        for material in &data.materials {
+            self.material(material)?;
+        }
+
+        self.len(data.parts.len())?;
+        for part in &data.parts {
+            self.string(&part.name)?;
+            self.index(part.parent)?;
+            self.len(part.submeshes.len())?;
+            for submesh in &part.submeshes {
+                self.index(submesh.material)?;
+                self.len(submesh.range.start)?;
+                self.len(submesh.range.end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Reader<R: Read>(R);
+
+impl<R: Read> Reader<R> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CacheError> {
+        let mut bytes = [0; N];
+        self.0.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, CacheError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, CacheError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn f32s<const N: usize>(&mut self) -> Result<[f32; N], CacheError> {
+        let mut values = [0.0; N];
+        for value in &mut values {
+            *value = self.f32()?;
+        }
+        Ok(values)
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, CacheError> {
+        Ok(Vec3::from(self.f32s::<3>()?))
+    }
+
+    fn len(&mut self) -> Result<usize, CacheError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn index(&mut self) -> Result<Option<usize>, CacheError> {
+        let index = self.u32()?;
+        Ok((index != NONE).then_some(index as usize))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, CacheError> {
+        let len = self.len()?;
+        let mut bytes = vec![];
+        (&mut self.0).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(CacheError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, CacheError> {
+        String::from_utf8(self.bytes()?).map_err(|_| CacheError::Corrupt("invalid string"))
+    }
+
+    fn texture(&mut self) -> Result<Option<TextureSource>, CacheError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(TextureSource::File(self.string()?.into()))),
+            2 => {
+                let width = self.u32()?;
+                let height = self.u32()?;
+                let image = RgbaImage::from_raw(width, height, self.bytes()?)
+                    .ok_or(CacheError::Corrupt("image smaller than its size"))?;
+                Ok(Some(TextureSource::Image(Rc::new(image))))
+            }
+            _ => Err(CacheError::Corrupt("unknown texture kind")),
+        }
+    }
+
+    fn material(&mut self) -> Result<Material, CacheError> {
+        Ok(Material {
+            name: self.string()?,
+            ambient: self.vec3()?,
+            diffuse: self.vec3()?,
+            specular: self.vec3()?,
+            shininess: self.f32()?,
+            emissive: self.vec3()?,
+            dissolve: self.f32()?,
+            illum: self.u32()?,
+            diffuse_map: self.texture()?,
+            bump_map: self.texture()?,
+        })
+    }
+
+    // Checks the magic and version and returns the files the model depends on
+    // with their modification times when the cache was written
+    fn header(&mut self) -> Result<Vec<(PathBuf, u64)>, CacheError> {
+        if &self.array::<4>()? != MAGIC {
+            return Err(CacheError::NotACache);
+        }
+        let version = self.u32()?;
+        if version != VERSION {
+            return Err(CacheError::UnsupportedVersion(version));
+        }
+
+        (0..self.len()?)
+            .map(|_| Ok((PathBuf::from(self.string()?), self.u64()?)))
+            .collect()
+    }
+
+    fn mesh_data(&mut self) -> Result<MeshData, CacheError> {
+        let dependencies = self.header()?.into_iter().map(|(path, _)| path).collect();
+
+        let bounds = Bounds {
+            min: self.vec3()?,
+            max: self.vec3()?,
+        };
+
+        let vertices = (0..self.len()?)
+            .map(|_| {
+                Ok(Vertex {
+                    position: self.f32s()?,
+                    normal: self.f32s()?,
+                    texture_coord: self.f32s()?,
+                })
+            })
+            .collect::<Result<Vec<_>, CacheError>>()?;
+
+        let indices = (0..self.len()?)
+            .map(|_| self.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        if indices.iter().any(|&i| i as usize >= vertices.len()) {
+            return Err(CacheError::Corrupt("index out of range"));
+        }
+
+        let materials = (0..self.len()?)
+            .map(|_| self.material())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut parts = vec![];
+        for part in 0..self.len()? {
+            let name = self.string()?;
+            let parent = self.index()?;
+            if parent.is_some_and(|parent| parent >= part) {
+                return Err(CacheError::Corrupt("part parent out of order"));
+            }
+
+            let mut submeshes = vec![];
+            for _ in 0..self.len()? {
+                let material = self.index()?;
+                let range = self.len()?..self.len()?;
+                if material.is_some_and(|material| material >= materials.len())
+                    || range.start > range.end
+                    || range.end > indices.len()
+                {
+                    return Err(CacheError::Corrupt("submesh out of range"));
+                }
+                submeshes.push(SubMeshData { material, range });
+            }
+
+            parts.push(PartData {
+                name,
+                parent,
+                submeshes,
+            });
+        }
+
+        Ok(MeshData {
+            vertices,
+            indices,
+            materials,
+            bounds,
+            parts,
+            dependencies,
+        })
+    }
+}
+
+pub fn save(data: &MeshData, path: &Path) -> Result<(), CacheError> {
+    let mut writer = Writer(BufWriter::new(File::create(path)?));
+    writer.mesh_data(data)?;
+    writer.0.flush()?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<MeshData, CacheError> {
+    Reader(BufReader::new(File::open(path)?)).mesh_data()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            position: [x, y, z],
+            normal: [0.0, 1.0, 0.0],
+            texture_coord: [x, z],
+        }
+    }
+
+    fn sample() -> MeshData {
+        let vertices = vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 1.0),
+            vertex(0.0, 2.0, 1.0),
+        ];
+        let image = RgbaImage::from_raw(1, 2, vec![1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        MeshData {
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices: vec![0, 1, 2, 0, 2, 3],
+            materials: vec![Material {
+                name: "Metal".to_string(),
+                diffuse: Vec3::new(0.5, 0.25, 0.125),
+                shininess: 32.0,
+                diffuse_map: Some(TextureSource::File("metal.png".into())),
+                bump_map: Some(TextureSource::Image(Rc::new(image))),
+                ..Default::default()
+            }],
+            parts: vec![
+                PartData {
+                    name: "Hull".to_string(),
+                    parent: None,
+                    submeshes: vec![SubMeshData {
+                        material: Some(0),
+                        range: 0..3,
+                    }],
+                },
+                PartData {
+                    name: "Turret".to_string(),
+                    parent: Some(0),
+                    submeshes: vec![SubMeshData {
+                        material: None,
+                        range: 3..6,
+                    }],
+                },
+            ],
+            dependencies: vec![PathBuf::from("missing.mtl")],
+        }
+    }
+
+    fn to_bytes(data: &MeshData) -> Vec<u8> {
+        let mut writer = Writer(vec![]);
+        writer.mesh_data(data).unwrap();
+        writer.0
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<MeshData, CacheError> {
+        Reader(Cursor::new(bytes)).mesh_data()
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = sample();
+        let loaded = from_bytes(to_bytes(&data)).unwrap();
+
+        assert_eq!(loaded.bounds, data.bounds);
+        assert_eq!(loaded.vertices, data.vertices);
+        assert_eq!(loaded.indices, data.indices);
+        assert_eq!(loaded.dependencies, data.dependencies);
+
+        let material = &loaded.materials[0];
+        assert_eq!(material.name, "Metal");
+        assert_eq!(material.diffuse, Vec3::new(0.5, 0.25, 0.125));
+        assert_eq!(material.shininess, 32.0);
+        assert!(
+            matches!(&material.diffuse_map, Some(TextureSource::File(path)) if path == Path::new("metal.png"))
+        );
+        match &material.bump_map {
+            Some(TextureSource::Image(image)) => {
+                assert_eq!(image.dimensions(), (1, 2));
+                assert_eq!(image.as_raw(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+            }
+            _ => panic!("bump map was not kept as an image"),
+        }
+
+        let parts: Vec<_> = loaded
+            .parts
+            .iter()
+            .map(|part| {
+                let submesh = &part.submeshes[0];
+                (
+                    part.name.as_str(),
+                    part.parent,
+                    submesh.material,
+                    submesh.range.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                ("Hull", None, Some(0), 0..3),
+                ("Turret", Some(0), None, 3..6)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        assert!(matches!(
+            from_bytes(b"# Blender OBJ File".to_vec()),
+            Err(CacheError::NotACache)
+        ));
+
+        let mut bytes = to_bytes(&sample());
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            from_bytes(bytes),
+            Err(CacheError::UnsupportedVersion(version)) if version == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let mut data = sample();
+        data.indices[5] = 4;
+        assert!(matches!(
+            from_bytes(to_bytes(&data)),
+            Err(CacheError::Corrupt("index out of range"))
+        ));
+
+        let mut data = sample();
+        data.parts[0].parent = Some(1);
+        assert!(matches!(
+            from_bytes(to_bytes(&data)),
+            Err(CacheError::Corrupt("part parent out of order"))
+        ));
+
+        let mut data = sample();
+        data.parts[1].submeshes[0].range = 3..7;
+        assert!(matches!(
+            from_bytes(to_bytes(&data)),
+            Err(CacheError::Corrupt("submesh out of range"))
+        ));
+
+        let mut bytes = to_bytes(&sample());
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(from_bytes(bytes), Err(CacheError::Io(_))));
+    }
+
+    #[test]
+    fn changed_material_library_makes_cache_stale() {
+        let dir = std::env::temp_dir().join(format!("topdown-mesh-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("model.obj");
+        let library = dir.join("model.mtl");
+        fs::write(
+            &model,
+            "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 0 1\nf 1 2 3\n",
+        )
+        .unwrap();
+        fs::write(&library, "newmtl Metal\nKd 1 0 0\n").unwrap();
+
+        // Explicit times, file systems may store them coarser than the test runs
+        let set_modified = |path: &Path, time: SystemTime| {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        let past = SystemTime::now() - Duration::from_secs(60);
+        set_modified(&model, past);
+        set_modified(&library, past);
+
+        let data = MeshData::load_source(model.clone()).unwrap();
+        assert_eq!(data.dependencies, [library.as_path()]);
+        save(&data, &cache_path(&model)).unwrap();
+        assert!(is_fresh(&model));
+
+        set_modified(&library, past + Duration::from_secs(30));
+        assert!(!is_fresh(&model));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
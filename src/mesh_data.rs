@@ -0,0 +1,287 @@
+use std::{fmt, ops::Range, path::PathBuf, rc::Rc};
+
+use nalgebra_glm::Vec3;
+
+use crate::{
+    gltf_loader::{self, GltfError},
+    material::{Material, TextureSource},
+    mesh_cache::{self, CacheError},
+    texture::sample_image,
+    vertex::Vertex,
+    wavefront::{self, ObjError},
+};
+
+#[derive(Debug)]
+pub enum MeshError {
+    Obj(ObjError),
+    Gltf(GltfError),
+    Cache(CacheError),
+    UnsupportedFormat(PathBuf),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Obj(err) => write!(f, "{}", err),
+            MeshError::Gltf(err) => write!(f, "{}", err),
+            MeshError::Cache(err) => write!(f, "{}", err),
+            MeshError::UnsupportedFormat(path) => {
+                write!(f, "unsupported model format: {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Obj(err) => Some(err),
+            MeshError::Gltf(err) => Some(err),
+            MeshError::Cache(err) => Some(err),
+            MeshError::UnsupportedFormat(_) => None,
+        }
+    }
+}
+
+impl From<ObjError> for MeshError {
+    fn from(err: ObjError) -> Self {
+        MeshError::Obj(err)
+    }
+}
+
+impl From<GltfError> for MeshError {
+    fn from(err: GltfError) -> Self {
+        MeshError::Gltf(err)
+    }
+}
+
+impl From<CacheError> for MeshError {
+    fn from(err: CacheError) -> Self {
+        MeshError::Cache(err)
+    }
+}
+
+// Axis aligned box enclosing a set of vertices
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    // An empty set of vertices gives an empty box at the origin
+    pub fn from_vertices<'a>(vertices: impl IntoIterator<Item = &'a Vertex>) -> Self {
+        let mut min = Vec3::repeat(f32::MAX);
+        let mut max = Vec3::repeat(f32::MIN);
+        for v in vertices {
+            let position = Vec3::from(v.position);
+            min = min.inf(&position);
+            max = max.sup(&position);
+        }
+
+        if min.x <= max.x {
+            Bounds { min, max }
+        } else {
+            Bounds {
+                min: Vec3::zeros(),
+                max: Vec3::zeros(),
+            }
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+}
+
+// Range of indices in a `MeshData` drawn with a single material
+pub struct SubMeshData {
+    // Index into `MeshData::materials`, `None` for faces without a material
+    pub material: Option<usize>,
+    pub range: Range<usize>,
+}
+
+// Named piece of a model, an `o` object or `g` group of an .obj file or a node of a glTF scene
+pub struct PartData {
+    pub name: String,
+    // Index of the part this one is attached to, parents come before their children
+    pub parent: Option<usize>,
+    pub submeshes: Vec<SubMeshData>,
+}
+
+// Geometry and materials of a mesh, loaded or built without a GPU and
+// uploaded with `Mesh::upload`
+pub struct MeshData {
+    // Unique combinations of position, normal and texture coordinate
+    pub vertices: Vec<Vertex>,
+    // Indexed triangle list, sorted so the faces of every part and material are contiguous
+    pub indices: Vec<u32>,
+    pub materials: Vec<Material>,
+    pub bounds: Bounds,
+    pub parts: Vec<PartData>,
+    // Files besides the model file the mesh was read from, like the material
+    // libraries of an .obj file. A change to any of them changes the mesh.
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl MeshData {
+    // Read a model from its binary cache when that is up to date, otherwise parse
+    // the model file with the loader matching its extension
+    pub fn load(path: PathBuf) -> Result<Self, MeshError> {
+        if mesh_cache::is_fresh(&path) {
+            let cache = mesh_cache::cache_path(&path);
+            match mesh_cache::load(&cache) {
+                Ok(data) => return Ok(data),
+                Err(err) => eprintln!("Ignoring mesh cache {}: {}", cache.display(), err),
+            }
+        }
+        MeshData::load_source(path)
+    }
+
+    // Parse a model file with the loader matching its extension, bypassing the cache
+    pub fn load_source(path: PathBuf) -> Result<Self, MeshError> {
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("obj") => Ok(wavefront::load(path)?),
+            Some("gltf") | Some("glb") => Ok(gltf_loader::load(&path)?),
+            _ => Err(MeshError::UnsupportedFormat(path)),
+        }
+    }
+
+    // Mesh of a single unnamed part drawn with a single material
+    fn with_material(vertices: Vec<Vertex>, indices: Vec<u32>, material: Material) -> Self {
+        let part = PartData {
+            name: String::new(),
+            parent: None,
+            submeshes: vec![SubMeshData {
+                material: Some(0),
+                range: 0..indices.len(),
+            }],
+        };
+        MeshData {
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices,
+            materials: vec![material],
+            parts: vec![part],
+            dependencies: vec![],
+        }
+    }
+
+    pub fn floor() -> Self {
+        let v_data = vec![
+            Vertex {
+                position: [10.0, 0.0, -10.0],
+                normal: [0.0, 1.0, 0.0],
+                texture_coord: [1.0, 0.0],
+            },
+            Vertex {
+                position: [10.0, 0.0, 10.0],
+                normal: [0.0, 1.0, 0.0],
+                texture_coord: [1.0, 1.0],
+            },
+            Vertex {
+                position: [-10.0, 0.0, -10.0],
+                normal: [0.0, 1.0, 0.0],
+                texture_coord: [0.0, 0.0],
+            },
+            Vertex {
+                position: [-10.0, 0.0, 10.0],
+                normal: [0.0, 1.0, 0.0],
+                texture_coord: [0.0, 1.0],
+            },
+        ];
+        let i_data = vec![0, 1, 2, 2, 1, 3];
+
+        let material = Material {
+            diffuse_map: Some(TextureSource::Image(Rc::new(sample_image()))),
+            ..Default::default()
+        };
+        MeshData::with_material(v_data, i_data, material)
+    }
+
+    // Unit cube shown in place of meshes that failed to load
+    pub fn placeholder() -> Self {
+        let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+            ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+        ];
+
+        let mut v_data = vec![];
+        let mut i_data = vec![];
+        for (normal, u, v) in faces {
+            let corner = |su: f32, sv: f32| Vertex {
+                position: [
+                    0.5 * (normal[0] + su * u[0] + sv * v[0]),
+                    0.5 * (normal[1] + su * u[1] + sv * v[1]),
+                    0.5 * (normal[2] + su * u[2] + sv * v[2]),
+                ],
+                normal,
+                texture_coord: [(su + 1.0) / 2.0, (sv + 1.0) / 2.0],
+            };
+            let first = v_data.len() as u32;
+            v_data.extend([
+                corner(-1.0, -1.0),
+                corner(1.0, -1.0),
+                corner(1.0, 1.0),
+                corner(-1.0, 1.0),
+            ]);
+            i_data.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+        }
+
+        let material = Material {
+            diffuse_map: Some(TextureSource::Image(Rc::new(sample_image()))),
+            ..Default::default()
+        };
+        MeshData::with_material(v_data, i_data, material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    #[test]
+    fn cube_corners_are_shared_per_face() {
+        let data = MeshData::load_source("cube.obj".into()).unwrap();
+        // Each corner has a normal per face it touches
+        assert_eq!(data.vertices.len(), 24);
+        assert_eq!(data.indices.len(), 36);
+        assert_eq!(
+            data.bounds,
+            Bounds {
+                min: vec3(-1.0, -1.0, -1.0),
+                max: vec3(1.0, 1.0, 1.0),
+            }
+        );
+    }
+
+    #[test]
+    fn flat_quad_shares_its_diagonal() {
+        let data = MeshData::load_source("triangle.obj".into()).unwrap();
+        assert_eq!(data.vertices.len(), 4);
+        assert_eq!(data.indices, [0, 1, 2, 0, 2, 3]);
+        assert!(data.vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+        assert_eq!(
+            data.bounds,
+            Bounds {
+                min: vec3(0.0, 0.0, 0.0),
+                max: vec3(1.0, 1.0, 0.0),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected() {
+        assert!(matches!(
+            MeshData::load_source("tank.png".into()),
+            Err(MeshError::UnsupportedFormat(_))
+        ));
+    }
+}
@@ -1,51 +1,111 @@
 use std::collections::HashSet;
 
-use glium::{
-    glutin::{
-        dpi::PhysicalPosition,
-        event::{ElementState, ModifiersState, MouseButton, MouseScrollDelta},
-    },
-    Display, VertexBuffer,
+use glium::glutin::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, ModifiersState, MouseButton, MouseScrollDelta},
 };
 use hecs::{Entity, World};
-use nalgebra_glm::{Vec2, Vec3, Vec4};
+use nalgebra_glm::{Vec2, Vec3};
+
+use crate::{
+    camera::{Camera, CameraRig},
+    math::Ray,
+    movement::Movement,
+    picking::{ground_point, screen_ray},
+    selectable::Selectable,
+};
 
-use crate::{camera::Camera, movement::Movement, selectable::Selectable, vertex::Vertex};
+// Pixels of touchpad scrolling that make up one line of a mouse wheel
+const PIXELS_PER_LINE: f32 = 20.0;
 
 pub struct MouseButtonState {
     pub left_pressed: bool,
     pub right_pressed: bool,
+    pub middle_pressed: bool,
 }
 
+// Cursor position and window size are both in physical pixels, which is what
+// cursor events report. On HiDPI screens they differ from the logical size.
 pub struct Mouse {
     screen_pos: Vec2,
-    max_width: u32,
-    max_height: u32,
+    screen_size: Vec2,
+    // Distance the cursor moved since the motion was last taken
+    motion: Vec2,
+    // Whether the cursor is over the window
+    pub in_window: bool,
     pub button_state: MouseButtonState,
 }
 
 impl Mouse {
-    pub fn new(screen_w: u32, screen_h: u32) -> Self {
+    pub fn new(size: PhysicalSize<u32>) -> Self {
         Mouse {
-            screen_pos: Vec2::new((screen_w / 2) as f32, (screen_h / 2) as f32),
-            max_width: screen_w,
-            max_height: screen_h,
+            screen_pos: Vec2::new((size.width / 2) as f32, (size.height / 2) as f32),
+            screen_size: Vec2::new(size.width as f32, size.height as f32),
+            motion: Vec2::zeros(),
+            in_window: false,
             button_state: MouseButtonState {
                 left_pressed: false,
                 right_pressed: false,
+                middle_pressed: false,
             },
         }
     }
 
     pub fn update(&mut self, pos: &PhysicalPosition<f64>) {
-        self.screen_pos.x = pos.x as f32;
-        self.screen_pos.y = pos.y as f32;
+        let pos = Vec2::new(pos.x as f32, pos.y as f32);
+        self.motion += pos - self.screen_pos;
+        self.screen_pos = pos;
+        self.in_window = true;
+    }
+
+    pub fn update_button(&mut self, button: MouseButton, state: ElementState) {
+        let pressed = state == ElementState::Pressed;
+        match button {
+            MouseButton::Left => self.button_state.left_pressed = pressed,
+            MouseButton::Right => self.button_state.right_pressed = pressed,
+            MouseButton::Middle => self.button_state.middle_pressed = pressed,
+            MouseButton::Other(_) => {}
+        }
+    }
+
+    // Distance the cursor moved since the last call
+    pub fn take_motion(&mut self) -> Vec2 {
+        std::mem::replace(&mut self.motion, Vec2::zeros())
+    }
+
+    // Which edges of the window the cursor is within `margin` pixels of, -1 or
+    // 1 on x for the left or right edge and on y for the bottom or top edge
+    pub fn edge(&self, margin: f32) -> Vec2 {
+        if !self.in_window {
+            return Vec2::zeros();
+        }
+        let side = |pos: f32, size: f32| {
+            if pos < margin {
+                -1.0
+            } else if pos > size - margin {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        Vec2::new(
+            side(self.screen_pos.x, self.screen_size.x),
+            -side(self.screen_pos.y, self.screen_size.y),
+        )
+    }
+
+    // The window was resized or moved to a screen with another scale factor
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.screen_size = Vec2::new(size.width as f32, size.height as f32);
     }
 }
 
 // Entity tag component
 pub struct Cursor {
+    // Point on the ground under the cursor
     pub position: Vec3,
+    // Ray from the camera through the cursor, None until the mouse is used
+    pub ray: Option<Ray>,
 }
 
 pub fn cursor_system(
@@ -54,70 +114,33 @@ pub fn cursor_system(
     cursor_entity: Entity,
     camera_entity: Entity,
 ) {
+    // Nothing to point at in a minimized window
+    if mouse.screen_size.x <= 0.0 || mouse.screen_size.y <= 0.0 {
+        return;
+    }
+
     let camera = world.get::<&Camera>(camera_entity).unwrap();
     let mut cursor = world.get::<&mut Cursor>(cursor_entity).unwrap();
 
-    let ndc = Vec3::new(
-        ((mouse.screen_pos.x as f32) / (mouse.max_width as f32)) * 2.0 - 1.0,
-        1.0 - ((mouse.screen_pos.y as f32) / (mouse.max_height as f32)) * 2.0,
-        0.0,
-    );
-
-    // Matrices needed
-    let inv_projection = camera
-        .projection
-        .try_inverse()
-        .expect("Failed to invert projection matrix");
-    let inv_view = camera
-        .view
-        .try_inverse()
-        .expect("Failed to invert view matrix");
-
-    let hcc = Vec4::new(ndc.x, ndc.y, -1.0, 1.0);
-
-    let mut eye = inv_projection * hcc;
-    eye.w = 0.0;
-
-    let ray = inv_view * eye;
-    let dir = ray.xyz().normalize();
-
-    let t = -camera.position.y / dir.y;
-    let x = camera.position.x + dir.x * t;
-    let z = camera.position.z + dir.z * t;
-    let y = 0.0;
-
-    cursor.position.x = x;
-    cursor.position.y = y;
-    cursor.position.z = z;
+    let ray = screen_ray(&camera, mouse.screen_pos, mouse.screen_size);
+    cursor.ray = Some(ray);
+    if let Some(point) = ground_point(&ray) {
+        cursor.position = point;
+    }
 }
 
-pub fn create_cursor_vb(display: &Display) -> VertexBuffer<Vertex> {
-    VertexBuffer::new(
-        display,
-        &[
-            Vertex {
-                position: [-0.2, 0.0, 0.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 0.0],
-            },
-            Vertex {
-                position: [0.2, 0.0, 0.0],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 0.0],
-            },
-            Vertex {
-                position: [0.0, 0.0, -0.2],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 0.0],
-            },
-            Vertex {
-                position: [0.0, 0.0, 0.2],
-                normal: [0.0, 1.0, 0.0],
-                texture_coord: [0.0, 0.0],
-            },
+// Cross on the ground marking where the cursor points
+pub fn cursor_lines(position: Vec3) -> [[Vec3; 2]; 2] {
+    [
+        [
+            position + Vec3::new(-0.2, 0.0, 0.0),
+            position + Vec3::new(0.2, 0.0, 0.0),
+        ],
+        [
+            position + Vec3::new(0.0, 0.0, -0.2),
+            position + Vec3::new(0.0, 0.0, 0.2),
         ],
-    )
-    .expect("Failed to create vertex buffer for debug point")
+    ]
 }
 
 pub fn mouse_click_system(
@@ -129,47 +152,40 @@ pub fn mouse_click_system(
     cursor_entity: Entity,
 ) {
     match button {
-        MouseButton::Left => {
-            if state == ElementState::Released {
-                for (id, (selectable,)) in world.query_mut::<(&mut Selectable,)>() {
-                    if selectable.hover {
-                        selectable.selected = true;
-                        selected.insert(id);
-                    } else {
-                        if !modifiers.shift() {
-                            selectable.selected = false;
-                            selected.remove(&id);
-                        }
-                    }
+        MouseButton::Left if state == ElementState::Released => {
+            for (id, (selectable,)) in world.query_mut::<(&mut Selectable,)>() {
+                if selectable.hover {
+                    selectable.selected = true;
+                    selected.insert(id);
+                } else if !modifiers.shift() {
+                    selectable.selected = false;
+                    selected.remove(&id);
                 }
             }
         }
-        MouseButton::Right => {
-            if state == ElementState::Released {
-                let cursor = world.get::<&Cursor>(cursor_entity).unwrap();
-                world
-                    .query::<(&mut Movement, &Selectable)>()
-                    .iter()
-                    .for_each(|(_, (movement, selectable))| {
-                        if selectable.selected {
-                            movement.target_pos = Some(cursor.position.xz());
-                        }
-                    });
-            }
+        MouseButton::Right if state == ElementState::Released => {
+            let cursor = world.get::<&Cursor>(cursor_entity).unwrap();
+            world
+                .query::<(&mut Movement, &Selectable)>()
+                .iter()
+                .for_each(|(_, (movement, selectable))| {
+                    if selectable.selected {
+                        movement.target_pos = Some(cursor.position.xz());
+                    }
+                });
         }
         _ => {}
     }
 }
 
+// Zoom the camera in when scrolling up and out when scrolling down
 pub fn mouse_scroll_system(world: &mut World, delta: MouseScrollDelta, camera_entity: Entity) {
-    match delta {
-        MouseScrollDelta::LineDelta(_, y) => {
-            let mut camera = world.get::<&mut Camera>(camera_entity).unwrap();
-            camera.position.y -= y;
-            camera.update_view();
-        }
-        MouseScrollDelta::PixelDelta(PhysicalPosition { .. }) => {
-            panic!("Scroll only implemented for line based input devices");
-        }
-    }
+    let lines = match delta {
+        MouseScrollDelta::LineDelta(_, y) => y,
+        MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => y as f32 / PIXELS_PER_LINE,
+    };
+    world
+        .get::<&mut CameraRig>(camera_entity)
+        .unwrap()
+        .zoom(lines);
 }
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use nalgebra_glm::Vec3;
+
+use crate::vertex::Vertex;
+
+// Smooth normals are only averaged across faces meeting at less than this angle
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
+fn face_normal(triangle: &[Vertex]) -> Vec3 {
+    let a = Vec3::from(triangle[0].position);
+    let b = Vec3::from(triangle[1].position);
+    let c = Vec3::from(triangle[2].position);
+    normalize_or_up((b - a).cross(&(c - a)))
+}
+
+// Angle of a triangle at the given corner
+fn corner_angle(triangle: &[Vertex], corner: usize) -> f32 {
+    let p = Vec3::from(triangle[corner].position);
+    let a = Vec3::from(triangle[(corner + 1) % 3].position) - p;
+    let b = Vec3::from(triangle[(corner + 2) % 3].position) - p;
+    if a.norm() > f32::EPSILON && b.norm() > f32::EPSILON {
+        nalgebra_glm::angle(&a, &b)
+    } else {
+        0.0
+    }
+}
+
+fn normalize_or_up(normal: Vec3) -> Vec3 {
+    if normal.norm() > f32::EPSILON {
+        normal.normalize()
+    } else {
+        Vec3::y()
+    }
+}
+
+// Give every vertex of a triangle list the normal of the triangle it belongs to.
+pub fn flat(vertices: &mut [Vertex]) {
+    for triangle in vertices.chunks_exact_mut(3) {
+        let normal: [f32; 3] = face_normal(triangle).into();
+        triangle.iter_mut().for_each(|v| v.normal = normal);
+    }
+}
+
+// Average the normals of triangles sharing a vertex position, weighted by the
+// angle each triangle makes at that vertex so the result doesn't depend on how
+// the faces were triangulated.
+// `smoothing_groups` holds one group per triangle as set by the `s` command
+// of an .obj file. Triangles are only smoothed with others in the same group,
+// group 0 means smoothing is off and the triangle is shaded flat. Neighbours
+// whose normals differ by more than `crease_angle` (radians) keep a hard edge.
+pub fn smooth(vertices: &mut [Vertex], smoothing_groups: &[u32], crease_angle: f32) {
+    let face_normals: Vec<Vec3> = vertices.chunks_exact(3).map(face_normal).collect();
+
+    // Corners at each position, keyed by the exact bits of the position
+    let mut adjacent: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (i, v) in vertices.iter().enumerate() {
+        adjacent
+            .entry(v.position.map(f32::to_bits))
+            .or_default()
+            .push(i);
+    }
+
+    let min_cos = crease_angle.cos();
+    let normals: Vec<[f32; 3]> = (0..vertices.len())
+        .map(|i| {
+            let triangle = i / 3;
+            let group = smoothing_groups[triangle];
+            if group == 0 {
+                return face_normals[triangle].into();
+            }
+
+            let normal: Vec3 = adjacent[&vertices[i].position.map(f32::to_bits)]
+                .iter()
+                .filter(|&&corner| {
+                    let other = corner / 3;
+                    smoothing_groups[other] == group
+                        && face_normals[triangle].dot(&face_normals[other]) >= min_cos
+                })
+                .map(|&corner| {
+                    let other = corner / 3;
+                    let angle = corner_angle(&vertices[other * 3..other * 3 + 3], corner % 3);
+                    face_normals[other] * angle
+                })
+                .sum();
+            if normal.norm() > f32::EPSILON {
+                normal.normalize().into()
+            } else {
+                face_normals[triangle].into()
+            }
+        })
+        .collect();
+
+    for (v, normal) in vertices.iter_mut().zip(normals) {
+        v.normal = normal;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    fn vertex(position: Vec3) -> Vertex {
+        Vertex {
+            position: position.into(),
+            normal: [0.0, 0.0, 0.0],
+            texture_coord: [0.0, 0.0],
+        }
+    }
+
+    // A flat triangle facing up and one folded up by `fold` radians along
+    // their shared edge from the origin to (1, 0, 0)
+    fn folded(fold: f32) -> Vec<Vertex> {
+        [
+            Vec3::zeros(),
+            Vec3::x(),
+            vec3(0.0, 0.0, -1.0),
+            Vec3::zeros(),
+            vec3(0.0, fold.sin(), fold.cos()),
+            Vec3::x(),
+        ]
+        .map(vertex)
+        .to_vec()
+    }
+
+    fn folded_normal(fold: f32) -> Vec3 {
+        vec3(0.0, fold.cos(), -fold.sin())
+    }
+
+    fn assert_normal(vertex: &Vertex, expected: Vec3) {
+        let normal = Vec3::from(vertex.normal);
+        assert!(
+            (normal - expected).norm() < 1e-5,
+            "{:?} != {:?}",
+            normal,
+            expected
+        );
+    }
+
+    #[test]
+    fn flat_uses_the_face_normal() {
+        let fold = PI / 6.0;
+        let mut vertices = folded(fold);
+        flat(&mut vertices);
+        vertices[..3]
+            .iter()
+            .for_each(|v| assert_normal(v, Vec3::y()));
+        vertices[3..]
+            .iter()
+            .for_each(|v| assert_normal(v, folded_normal(fold)));
+    }
+
+    #[test]
+    fn shared_corners_are_averaged() {
+        let fold = PI / 6.0;
+        let mut vertices = folded(fold);
+        smooth(&mut vertices, &[1, 1], DEFAULT_CREASE_ANGLE);
+
+        let average = (Vec3::y() + folded_normal(fold)).normalize();
+        for i in [0, 1, 3, 5] {
+            assert_normal(&vertices[i], average);
+        }
+        // Corners only one triangle touches keep its normal
+        assert_normal(&vertices[2], Vec3::y());
+        assert_normal(&vertices[4], folded_normal(fold));
+    }
+
+    #[test]
+    fn group_zero_is_flat() {
+        let fold = PI / 6.0;
+        let mut vertices = folded(fold);
+        smooth(&mut vertices, &[0, 0], DEFAULT_CREASE_ANGLE);
+        vertices[..3]
+            .iter()
+            .for_each(|v| assert_normal(v, Vec3::y()));
+        vertices[3..]
+            .iter()
+            .for_each(|v| assert_normal(v, folded_normal(fold)));
+    }
+
+    #[test]
+    fn different_groups_are_not_smoothed_together() {
+        let fold = PI / 6.0;
+        let mut vertices = folded(fold);
+        smooth(&mut vertices, &[1, 2], DEFAULT_CREASE_ANGLE);
+        vertices[..3]
+            .iter()
+            .for_each(|v| assert_normal(v, Vec3::y()));
+        vertices[3..]
+            .iter()
+            .for_each(|v| assert_normal(v, folded_normal(fold)));
+    }
+
+    #[test]
+    fn crease_angle_keeps_right_angles_sharp() {
+        let fold = PI / 2.0;
+        let mut vertices = folded(fold);
+        smooth(&mut vertices, &[1, 1], DEFAULT_CREASE_ANGLE);
+        vertices[..3]
+            .iter()
+            .for_each(|v| assert_normal(v, Vec3::y()));
+        vertices[3..]
+            .iter()
+            .for_each(|v| assert_normal(v, folded_normal(fold)));
+
+        // A wider crease angle smooths the same edge
+        let mut vertices = folded(fold);
+        smooth(&mut vertices, &[1, 1], PI * 0.75);
+        let average = (Vec3::y() + folded_normal(fold)).normalize();
+        assert_normal(&vertices[0], average);
+        assert_normal(&vertices[3], average);
+    }
+
+    #[test]
+    fn degenerate_triangles_face_up() {
+        let line = [Vec3::zeros(), Vec3::x(), vec3(2.0, 0.0, 0.0)];
+        let point = [Vec3::x(); 3];
+        let mut vertices: Vec<Vertex> = line.into_iter().chain(point).map(vertex).collect();
+
+        flat(&mut vertices);
+        vertices.iter().for_each(|v| assert_normal(v, Vec3::y()));
+
+        smooth(&mut vertices, &[1, 1], DEFAULT_CREASE_ANGLE);
+        vertices.iter().for_each(|v| assert_normal(v, Vec3::y()));
+    }
+}
@@ -0,0 +1,173 @@
+use hecs::{Entity, World};
+use nalgebra_glm::{vec2, vec3, vec4, Mat4, Vec2, Vec3};
+
+use crate::{
+    assets::{Assets, MeshId},
+    camera::{Camera, ProjectionKind},
+    math::Ray,
+    mesh::{Mesh, PartPose},
+    selectable::Selectable,
+    transformation::Transformation,
+};
+
+// What a ray hit first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub entity: Entity,
+    pub point: Vec3,
+    // Distance from the ray's origin
+    pub distance: f32,
+}
+
+// Ray from the camera through a pixel. `pixel` is measured from the top left
+// corner of a screen of `screen_size`, both in the same units. Perspective
+// rays all start at the camera, orthographic rays are parallel and start at
+// a different point on the near plane for every pixel.
+pub fn screen_ray(camera: &Camera, pixel: Vec2, screen_size: Vec2) -> Ray {
+    let ndc = vec2(
+        pixel.x / screen_size.x * 2.0 - 1.0,
+        1.0 - pixel.y / screen_size.y * 2.0,
+    );
+    let inverse = (camera.projection * camera.view)
+        .try_inverse()
+        .unwrap_or_else(Mat4::identity);
+    let unproject = |z: f32| {
+        let point = inverse * vec4(ndc.x, ndc.y, z, 1.0);
+        point.xyz() / point.w
+    };
+    let far = unproject(1.0);
+    let origin = match camera.kind() {
+        ProjectionKind::Perspective => camera.position,
+        ProjectionKind::Orthographic => unproject(-1.0),
+    };
+    Ray::new(origin, far - origin)
+}
+
+// Where the ray meets the ground, if it points down at it
+pub fn ground_point(ray: &Ray) -> Option<Vec3> {
+    ray.intersect_plane(Vec3::zeros(), Vec3::y())
+        .map(|t| ray.at(t))
+}
+
+// Closest triangle of a placed mesh the ray hits. Each part is tested in its
+// own model space, after checking the ray passes through the mesh's bounds.
+pub fn intersect_mesh(ray: &Ray, mesh: &Mesh, model: Mat4, pose: Option<&PartPose>) -> Option<f32> {
+    let data = &mesh.data;
+    let mut closest: Option<f32> = None;
+    for (part, part_model) in mesh.parts.iter().zip(mesh.part_models(model, pose)) {
+        let Some(inverse) = part_model.try_inverse() else {
+            continue;
+        };
+        let local = ray.transform(&inverse);
+        if local
+            .intersect_aabb(data.bounds.min, data.bounds.max)
+            .is_none()
+        {
+            continue;
+        }
+
+        for submesh in &part.submeshes {
+            for triangle in data.indices[submesh.range.clone()].chunks_exact(3) {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| Vec3::from(data.vertices[triangle[i] as usize].position));
+                if let Some(t) = local.intersect_triangle(a, b, c) {
+                    closest = Some(closest.map_or(t, |closest| closest.min(t)));
+                }
+            }
+        }
+    }
+    closest
+}
+
+// Closest selectable entity the ray hits. Entities with a mesh are hit by
+// their triangles, others by their bounding circle on the ground.
+pub fn pick(world: &World, assets: &Assets, ray: &Ray) -> Option<Hit> {
+    let mut closest: Option<Hit> = None;
+    for (entity, (selectable, transformation, mesh, pose)) in world
+        .query::<(
+            &Selectable,
+            &Transformation,
+            Option<&MeshId>,
+            Option<&PartPose>,
+        )>()
+        .iter()
+    {
+        let distance = match mesh {
+            Some(mesh) => intersect_mesh(ray, assets.mesh(mesh), transformation.model(), pose),
+            None => {
+                let circle = selectable.bounding_circle;
+                let center = transformation.pos
+                    + vec3(circle.ground_pos.x, 0.0, circle.ground_pos.y) * transformation.scale;
+                ray.intersect_circle(center, circle.r * transformation.scale)
+            }
+        };
+
+        if let Some(distance) = distance {
+            if closest.is_none_or(|closest| distance < closest.distance) {
+                closest = Some(Hit {
+                    entity,
+                    point: ray.at(distance),
+                    distance,
+                });
+            }
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraRig;
+
+    const SCREEN: Vec2 = Vec2::new(800.0, 600.0);
+    const PIXELS: [Vec2; 3] = [
+        Vec2::new(400.0, 300.0),
+        Vec2::new(10.0, 20.0),
+        Vec2::new(790.0, 550.0),
+    ];
+
+    fn camera(kind: ProjectionKind) -> Camera {
+        let rig = CameraRig::new(
+            Vec3::zeros(),
+            std::f32::consts::FRAC_PI_4,
+            20.0,
+            Vec2::repeat(-10.0),
+            Vec2::repeat(10.0),
+        );
+        let mut camera = Camera::new(0.1, 50.0, SCREEN.x as u32, SCREEN.y as u32);
+        camera.set_kind(kind);
+        rig.apply(&mut camera);
+        camera
+    }
+
+    #[test]
+    fn perspective_rays_start_at_the_camera() {
+        let camera = camera(ProjectionKind::Perspective);
+        let rays = PIXELS.map(|pixel| screen_ray(&camera, pixel, SCREEN));
+        for ray in &rays {
+            assert!((ray.origin - camera.position).norm() < 1e-4);
+        }
+        assert!(rays[0].direction.angle(&rays[1].direction) > 0.1);
+        assert!(rays[1].direction.angle(&rays[2].direction) > 0.1);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = camera(ProjectionKind::Orthographic);
+        let rays = PIXELS.map(|pixel| screen_ray(&camera, pixel, SCREEN));
+        for ray in &rays[1..] {
+            assert!((ray.direction - rays[0].direction).norm() < 1e-4);
+            assert!((ray.origin - rays[0].origin).norm() > 1.0);
+        }
+    }
+
+    #[test]
+    fn middle_of_the_screen_hits_the_focus() {
+        for kind in [ProjectionKind::Perspective, ProjectionKind::Orthographic] {
+            let ray = screen_ray(&camera(kind), SCREEN / 2.0, SCREEN);
+            let point = ground_point(&ray).unwrap();
+            assert!(point.norm() < 1e-3, "{:?} hits {}", kind, point);
+        }
+    }
+}
@@ -1,159 +1,406 @@
+use std::{collections::HashMap, ops::Range};
+
 use glium::{
-    index::{NoIndices, PrimitiveType},
-    texture::SrgbTexture2d,
-    uniform, BackfaceCullingMode, DepthTest, Display, DrawParameters, Frame, Program, Surface,
-    VertexBuffer,
+    backend::Facade,
+    framebuffer::SimpleFrameBuffer,
+    index::{IndicesSource, NoIndices, PrimitiveType},
+    uniform,
+    vertex::{VertexBufferSlice, VerticesSource},
+    BackfaceCullingMode, DepthTest, Display, DrawParameters, Program, Surface,
 };
 use hecs::{Entity, World};
 use nalgebra_glm::{vec3, Mat4, Vec3};
 
 use crate::{
+    assets::{Assets, MeshId, ShaderId},
     camera::Camera,
-    light::Light,
-    mesh_repo::{MeshId, MeshRepo},
-    mouse::{create_cursor_vb, Cursor},
+    debug_draw::DebugDraw,
+    light::{closest_lights, Light, LightKind, LightUniforms},
+    material::Material,
+    mesh::PartPose,
+    mouse::{cursor_lines, Cursor},
+    render_cache::RenderCache,
+    renderer::Renderer,
     selectable::Selectable,
+    shader::ShaderError,
+    shadow::{self, ShadowMap},
+    texture::Texture,
     transformation::Transformation,
-    vertex::Vertex,
+    vertex::{Instance, LineVertex, Vertex},
 };
 
+// Shader programs the scene is drawn with
+pub struct Shaders {
+    pub scene: ShaderId,
+    pub shadow: ShaderId,
+    pub lines: ShaderId,
+}
+
+impl Shaders {
+    pub fn load(facade: &impl Facade, assets: &mut Assets) -> Result<Self, ShaderError> {
+        Ok(Shaders {
+            scene: assets.load_shader(facade, "vertex.glsl", "fragment.glsl")?,
+            shadow: assets.load_shader(facade, "shadow_vertex.glsl", "shadow_fragment.glsl")?,
+            lines: assets.load_shader(facade, "line_vertex.glsl", "line_fragment.glsl")?,
+        })
+    }
+}
+
+// Draw a frame to the window. Debug lines meant for one frame are dropped once
+// it is shown.
 pub fn render_system(
     display: &Display,
-    mesh_repo: &mut MeshRepo,
+    assets: &Assets,
+    render_cache: &mut RenderCache,
     world: &World,
-    shader: &Program,
+    debug_draw: &mut DebugDraw,
+    shaders: &Shaders,
     camera_entity: Entity,
 ) {
     let mut frame = display.draw();
-    frame.clear(None, Some((0.8, 0.7, 0.6, 1.0)), true, Some(1.0), Some(0));
+    render_scene(
+        display,
+        &mut frame,
+        assets,
+        render_cache,
+        world,
+        debug_draw,
+        shaders,
+        camera_entity,
+    );
+    frame.finish().expect("Falied to draw to screen");
+    debug_draw.end_frame();
+}
 
-    let camera = world.get::<&Camera>(camera_entity).unwrap();
+// Render the world as seen by the camera into any target, like the window
+// or an offscreen framebuffer. The target needs a depth buffer.
+#[allow(clippy::too_many_arguments)]
+pub fn render_scene(
+    facade: &impl Facade,
+    target: &mut impl Surface,
+    assets: &Assets,
+    render_cache: &mut RenderCache,
+    world: &World,
+    debug_draw: &DebugDraw,
+    shaders: &Shaders,
+    camera_entity: Entity,
+) {
+    let mut renderer = GliumRenderer::new(facade, target, assets, render_cache, shaders);
+    submit_scene(&mut renderer, world, debug_draw, camera_entity);
+}
 
+// Submit everything in the world to a renderer and present it
+pub fn submit_scene(
+    renderer: &mut impl Renderer,
+    world: &World,
+    debug_draw: &DebugDraw,
+    camera_entity: Entity,
+) {
+    for (_id, (mesh_id, transformation, pose)) in world
+        .query::<(&MeshId, &Transformation, Option<&PartPose>)>()
+        .iter()
+    {
+        renderer.submit_mesh(mesh_id, transformation.model(), pose);
+    }
+
+    // Render bounding circles around selectables
+    for (_id, (transformation, selectable)) in
+        world.query::<(&Transformation, &Selectable)>().iter()
+    {
+        if selectable.hover || selectable.selected {
+            let bc_color = if selectable.hover {
+                vec3(0.1, 0.1, 0.7)
+            } else {
+                vec3(0.1, 0.1, 0.1)
+            };
+
+            let bc_model =
+                Mat4::new_translation(&vec3(transformation.pos.x, 0.1, transformation.pos.z))
+                    * Mat4::new_scaling(transformation.scale);
+
+            let strip = selectable
+                .bounding_circle
+                .triangle_strip(24, 0.1 / transformation.scale);
+            renderer.submit_overlay(&strip, bc_model, bc_color);
+        }
+    }
+
+    // Draw cursor
+    for (_id, (cursor,)) in world.query::<(&Cursor,)>().iter() {
+        renderer.submit_lines(&cursor_lines(cursor.position), vec3(1.0, 0.0, 0.0));
+    }
+    debug_draw.submit(renderer);
+
+    let camera = world.get::<&Camera>(camera_entity).unwrap();
     let mut lights = world.query::<(&Light,)>();
     let lights: Vec<&Light> = lights.iter().map(|(_, (l,))| l).collect();
+    renderer.present(&camera, &lights);
+}
 
-    // Render meshes
-    world.query::<(&MeshId, &Transformation)>().iter().for_each(
-        |(_id, (mesh_id, transformation))| {
-            let mesh = mesh_repo
-                .get(mesh_id)
-                .expect("MeshId does not correspond to any mesh in repo");
-
-            render_vertex_buffer(
-                &mut frame,
-                &mesh.vertex_buffer,
-                glium::index::PrimitiveType::TrianglesList,
-                &shader,
-                transformation.model(),
-                &camera,
-                lights[0],
-                mesh.color,
-                &mesh.texture,
-            );
-        },
-    );
+// Renderer drawing with OpenGL into a window or offscreen target. Meshes are
+// batched by mesh and drawn with instancing.
+pub struct GliumRenderer<'a, F: Facade, S: Surface> {
+    facade: &'a F,
+    target: &'a mut S,
+    assets: &'a Assets,
+    render_cache: &'a mut RenderCache,
+    shaders: &'a Shaders,
+    // Model matrices of the parts of every submitted mesh, grouped by mesh
+    meshes: HashMap<MeshId, Vec<Vec<Mat4>>>,
+    overlay_vertices: Vec<Vertex>,
+    overlays: Vec<(Range<usize>, Instance)>,
+    line_vertices: Vec<LineVertex>,
+}
 
-    // Render bounding circles around selectables
-    world
-        .query::<(&Transformation, &Selectable)>()
-        .iter()
-        .for_each(|(_id, (transformation, selectable))| {
-            if selectable.hover || selectable.selected {
-                let bc_vertex_buffer = VertexBuffer::new(
-                    display,
-                    &selectable
-                        .bounding_circle
-                        .triangle_strip(24, 0.1 / transformation.scale),
-                )
-                .unwrap();
-
-                let bc_color = if selectable.hover {
-                    vec3(0.1, 0.1, 0.7)
-                } else {
-                    vec3(0.1, 0.1, 0.1)
-                };
-
-                let bc_model =
-                    Mat4::new_translation(&vec3(transformation.pos.x, 0.1, transformation.pos.z))
-                        * Mat4::new_scaling(transformation.scale);
-
-                render_vertex_buffer(
-                    &mut frame,
-                    &bc_vertex_buffer,
-                    glium::index::PrimitiveType::TriangleStrip,
-                    &shader,
-                    bc_model,
-                    &camera,
-                    lights[0],
-                    bc_color,
-                    // TODO: Don't recreate and drop textures every render
-                    &SrgbTexture2d::empty(display, 1, 1).unwrap(),
+impl<'a, F: Facade, S: Surface> GliumRenderer<'a, F, S> {
+    pub fn new(
+        facade: &'a F,
+        target: &'a mut S,
+        assets: &'a Assets,
+        render_cache: &'a mut RenderCache,
+        shaders: &'a Shaders,
+    ) -> Self {
+        GliumRenderer {
+            facade,
+            target,
+            assets,
+            render_cache,
+            shaders,
+            meshes: HashMap::new(),
+            overlay_vertices: vec![],
+            overlays: vec![],
+            line_vertices: vec![],
+        }
+    }
+}
+
+impl<F: Facade, S: Surface> Renderer for GliumRenderer<'_, F, S> {
+    fn submit_mesh(&mut self, mesh: &MeshId, model: Mat4, pose: Option<&PartPose>) {
+        let part_models = self.assets.mesh(mesh).part_models(model, pose);
+        self.meshes
+            .entry(mesh.clone())
+            .or_default()
+            .push(part_models);
+    }
+
+    fn submit_overlay(&mut self, strip: &[Vertex], model: Mat4, color: Vec3) {
+        let start = self.overlay_vertices.len();
+        self.overlay_vertices.extend_from_slice(strip);
+        self.overlays.push((
+            start..self.overlay_vertices.len(),
+            Instance::new(model, color),
+        ));
+    }
+
+    fn submit_lines(&mut self, lines: &[[Vec3; 2]], color: Vec3) {
+        self.line_vertices
+            .extend(lines.iter().flatten().map(|point| LineVertex {
+                position: (*point).into(),
+                color: color.into(),
+            }));
+    }
+
+    fn present(&mut self, camera: &Camera, lights: &[&Light]) {
+        let facade = self.facade;
+        let assets = self.assets;
+        let render_cache = &mut *self.render_cache;
+        let target = &mut *self.target;
+        target.clear(None, Some((0.8, 0.7, 0.6, 1.0)), true, Some(1.0), Some(0));
+
+        let lights = closest_lights(lights.to_vec(), camera.position);
+
+        // Overlays like selection rings and the cursor are only tinted by their color
+        let overlay_material = Material::default();
+
+        // The instances of every draw in the frame are written to one buffer, and
+        // each draw uses a range of it. Parts are posed separately, so every part
+        // of a mesh gets its own range.
+        let mut instances: Vec<Instance> = vec![];
+        let mut part_draws = vec![];
+        for (mesh_id, part_models) in self.meshes.drain() {
+            let mesh = assets.mesh(&mesh_id);
+            for (i, part) in mesh.parts.iter().enumerate() {
+                let start = instances.len();
+                instances.extend(
+                    part_models
+                        .iter()
+                        .map(|models| Instance::new(models[i], mesh.color)),
                 );
+                part_draws.push((mesh, part, start..instances.len()));
             }
-        });
+        }
 
-    frame.clear_depth(1.0);
-    // Draw cursor
-    world
-        .query::<(&Cursor,)>()
-        .iter()
-        .for_each(|(_, (cursor,))| {
-            // Model matrix for debug point is just a translation to the specified point
-            let model = Mat4::new_translation(&cursor.position);
-            let object_color = Vec3::new(1.0, 0.0, 0.0);
-
-            render_vertex_buffer(
-                &mut frame,
-                &create_cursor_vb(display),
-                glium::index::PrimitiveType::LinesList,
-                &shader,
-                model,
-                &camera,
-                lights[0],
-                object_color,
-                // TODO: Don't recreate and drop textures every render
-                &SrgbTexture2d::empty(display, 1, 1).unwrap(),
+        // Overlays get one instance each
+        let overlay_draws: Vec<_> = self
+            .overlays
+            .drain(..)
+            .map(|(vertices, instance)| {
+                instances.push(instance);
+                (vertices, instances.len() - 1..instances.len())
+            })
+            .collect();
+
+        render_cache.overlays.write(facade, &self.overlay_vertices);
+        render_cache.lines.write(facade, &self.line_vertices);
+        render_cache.instances.write(facade, &instances);
+        let line_count = self.line_vertices.len();
+        self.overlay_vertices.clear();
+        self.line_vertices.clear();
+
+        let shader = assets.shader(&self.shaders.scene);
+
+        // Render the shadow map from the first directional light, then meshes
+        // are lit by it wherever they are not in its shadow
+        let sun = lights
+            .iter()
+            .enumerate()
+            .find_map(|(i, light)| match light.kind {
+                LightKind::Directional { direction } => Some((i, direction)),
+                _ => None,
+            });
+        let settings = render_cache.shadow_map.settings;
+        let (shadow_light, light_space) = match sun {
+            Some((i, direction)) if settings.enabled => {
+                let light_space = shadow::light_space(direction, camera, &settings);
+                let mut shadow_target = render_cache.shadow_map.framebuffer(facade);
+                shadow_target.clear_depth(1.0);
+                for (mesh, part, range) in &part_draws {
+                    for submesh in &part.submeshes {
+                        render_shadow(
+                            &mut shadow_target,
+                            (&mesh.vertex_buffer).into(),
+                            &render_cache.instances.slice(range.clone()),
+                            mesh.index_buffer.slice(submesh.range.clone()),
+                            assets.shader(&self.shaders.shadow),
+                            light_space,
+                        );
+                    }
+                }
+                (i as i32, light_space)
+            }
+            _ => (-1, Mat4::identity()),
+        };
+
+        let scene = Scene {
+            camera,
+            lights: &lights,
+            shadow_light,
+            light_space,
+            shadow_map: &render_cache.shadow_map,
+        };
+
+        // Render meshes
+        for (mesh, part, range) in part_draws {
+            for submesh in &part.submeshes {
+                render_instances(
+                    target,
+                    (&mesh.vertex_buffer).into(),
+                    &render_cache.instances.slice(range.clone()),
+                    mesh.index_buffer.slice(submesh.range.clone()),
+                    shader,
+                    &scene,
+                    &submesh.material,
+                    assets.texture(&submesh.textures.diffuse),
+                    assets.texture(&submesh.textures.normal),
+                );
+            }
+        }
+
+        for (vertices, instance) in overlay_draws {
+            render_instances(
+                target,
+                render_cache.overlays.slice(vertices).into(),
+                &render_cache.instances.slice(instance),
+                NoIndices(PrimitiveType::TriangleStrip).into(),
+                shader,
+                &scene,
+                &overlay_material,
+                assets.white_texture(),
+                assets.normal_texture(),
             );
-        });
+        }
 
-    frame.finish().expect("Falied to draw to screen");
+        // Lines are drawn on top of everything, all at once
+        target.clear_depth(1.0);
+        if line_count > 0 {
+            render_lines(
+                target,
+                render_cache.lines.slice(0..line_count),
+                assets.shader(&self.shaders.lines),
+                camera,
+            );
+        }
+    }
 }
 
-fn render_vertex_buffer(
-    frame: &mut Frame,
-    vertices: &VertexBuffer<Vertex>,
-    primitive_type: PrimitiveType,
+// What every draw of a frame is seen and lit by
+struct Scene<'a> {
+    camera: &'a Camera,
+    lights: &'a [&'a Light],
+    // Index of the light casting shadows, -1 when nothing does
+    shadow_light: i32,
+    // View projection of the shadow casting light
+    light_space: Mat4,
+    shadow_map: &'a ShadowMap,
+}
+
+// Draw the vertices once for every instance, placed by the instance's model matrix
+#[allow(clippy::too_many_arguments)]
+fn render_instances(
+    target: &mut impl Surface,
+    vertices: VerticesSource,
+    instances: &VertexBufferSlice<Instance>,
+    indices: IndicesSource,
     shader: &Program,
-    model: Mat4,
-    camera: &Camera,
-    light: &Light,
-    object_color: Vec3,
-    texture: &SrgbTexture2d,
+    scene: &Scene,
+    material: &Material,
+    texture: &Texture,
+    normal_map: &Texture,
 ) {
-    let model: [[f32; 4]; 4] = model.into();
-    let view: [[f32; 4]; 4] = camera.view.into();
-    let projection: [[f32; 4]; 4] = camera.projection.into();
-    let light_pos: [f32; 3] = light.position.into();
-    let light_color: [f32; 3] = light.color.into();
-    let object_color: [f32; 3] = object_color.into();
-
-    let texture = texture
-        .sampled()
-        .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear);
-    let uniforms = uniform! {
-        model: model,
-        view: view,
-        projection: projection,
-        light_pos: light_pos,
-        light_color: light_color,
-        object_color: object_color,
-        texture_sampler: texture
+    let view: [[f32; 4]; 4] = scene.camera.view.into();
+    let projection: [[f32; 4]; 4] = scene.camera.projection.into();
+    let light_space: [[f32; 4]; 4] = scene.light_space.into();
+    let camera_position: [f32; 3] = scene.camera.position.into();
+    let material_ambient: [f32; 3] = material.ambient.into();
+    let material_diffuse: [f32; 3] = material.diffuse.into();
+    // Illumination models below 2 have no highlights
+    let material_specular: [f32; 3] = if material.illum >= 2 {
+        material.specular.into()
+    } else {
+        [0.0; 3]
+    };
+    let material_emissive: [f32; 3] = material.emissive.into();
+
+    let uniforms = LightUniforms {
+        lights: scene.lights,
+        uniforms: uniform! {
+            view: view,
+            projection: projection,
+            camera_position: camera_position,
+            material_ambient: material_ambient,
+            material_diffuse: material_diffuse,
+            material_specular: material_specular,
+            material_shininess: material.shininess,
+            material_emissive: material_emissive,
+            material_dissolve: material.dissolve,
+            texture_sampler: texture.sampled(),
+            normal_sampler: normal_map.sampled(),
+            light_space: light_space,
+            shadow_light: scene.shadow_light,
+            shadow_bias: scene.shadow_map.settings.bias,
+            shadow_map: scene.shadow_map.sampled()
+        },
     };
-    frame
+    target
         .draw(
-            vertices,
-            &NoIndices(primitive_type),
-            &shader,
+            (
+                vertices,
+                instances
+                    .per_instance()
+                    .expect("Instancing is not supported"),
+            ),
+            indices,
+            shader,
             &uniforms,
             &DrawParameters {
                 backface_culling: BackfaceCullingMode::CullingDisabled,
@@ -168,3 +415,131 @@ fn render_vertex_buffer(
         )
         .expect("Failed to render");
 }
+
+// Draw the depth of the instances into the shadow map
+fn render_shadow(
+    target: &mut SimpleFrameBuffer,
+    vertices: VerticesSource,
+    instances: &VertexBufferSlice<Instance>,
+    indices: IndicesSource,
+    shader: &Program,
+    light_space: Mat4,
+) {
+    let light_space: [[f32; 4]; 4] = light_space.into();
+    target
+        .draw(
+            (
+                vertices,
+                instances
+                    .per_instance()
+                    .expect("Instancing is not supported"),
+            ),
+            indices,
+            shader,
+            &uniform! { light_space: light_space },
+            &DrawParameters {
+                depth: glium::Depth {
+                    test: DepthTest::IfLess,
+                    write: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .expect("Failed to render shadow map");
+}
+
+// Draw colored lines, unlit and without instancing
+fn render_lines(
+    target: &mut impl Surface,
+    vertices: VertexBufferSlice<LineVertex>,
+    shader: &Program,
+    camera: &Camera,
+) {
+    let view: [[f32; 4]; 4] = camera.view.into();
+    let projection: [[f32; 4]; 4] = camera.projection.into();
+    target
+        .draw(
+            vertices,
+            NoIndices(PrimitiveType::LinesList),
+            shader,
+            &uniform! { view: view, projection: projection },
+            &DrawParameters {
+                depth: glium::Depth {
+                    test: DepthTest::IfLess,
+                    write: true,
+                    ..Default::default()
+                },
+                line_width: Some(3.0),
+                ..Default::default()
+            },
+        )
+        .expect("Failed to render lines");
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec2;
+
+    use super::*;
+    use crate::{
+        bounding_circle::BoundingCircle,
+        renderer::{DrawCommand, RecordingRenderer},
+    };
+
+    // Colors of the overlays drawn for a unit that is hovered and selected as given
+    fn overlay_colors(hover: bool, selected: bool) -> Vec<Vec3> {
+        let mut world = World::new();
+        let camera_entity = world.spawn((Camera::new(0.1, 50.0, 800, 600),));
+        let mesh = MeshId::detached(0);
+        let selectable = Selectable {
+            selected,
+            hover,
+            bounding_circle: BoundingCircle {
+                r: 1.0,
+                ground_pos: Vec2::zeros(),
+            },
+        };
+        world.spawn((
+            mesh.clone(),
+            Transformation::new(vec3(2.0, 0.0, 3.0), 0.0, 1.0),
+            selectable,
+        ));
+
+        let mut renderer = RecordingRenderer::default();
+        submit_scene(&mut renderer, &world, &DebugDraw::default(), camera_entity);
+
+        assert!(renderer
+            .commands
+            .iter()
+            .any(|command| matches!(command, DrawCommand::Mesh { mesh: id, .. } if *id == mesh)));
+        assert!(matches!(
+            renderer.commands.last(),
+            Some(DrawCommand::Present { .. })
+        ));
+        renderer
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::Overlay { color, .. } => Some(*color),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hovered_units_get_a_blue_ring() {
+        assert_eq!(overlay_colors(true, false), [vec3(0.1, 0.1, 0.7)]);
+        assert_eq!(overlay_colors(true, true), [vec3(0.1, 0.1, 0.7)]);
+    }
+
+    #[test]
+    fn selected_units_get_a_dark_ring() {
+        assert_eq!(overlay_colors(false, true), [vec3(0.1, 0.1, 0.1)]);
+    }
+
+    #[test]
+    fn other_units_get_no_ring() {
+        assert!(overlay_colors(false, false).is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+use std::ops::Range;
+
+use glium::{backend::Facade, vertex::VertexBufferSlice, Vertex as GliumVertex, VertexBuffer};
+
+use crate::{
+    shadow::{ShadowMap, ShadowSettings},
+    vertex::{Instance, LineVertex, Vertex},
+};
+
+// Vertex buffer that is rewritten every frame. It only grows, doubling in
+// size, when what is written doesn't fit anymore.
+pub struct StreamBuffer<T: GliumVertex> {
+    buffer: VertexBuffer<T>,
+    len: usize,
+    name: &'static str,
+}
+
+impl<T: GliumVertex> StreamBuffer<T> {
+    fn new(display: &impl Facade, capacity: usize, name: &'static str) -> Self {
+        StreamBuffer {
+            buffer: VertexBuffer::empty_dynamic(display, capacity).unwrap_or_else(|err| {
+                panic!("Failed to create vertex buffer for {}: {}", name, err)
+            }),
+            len: 0,
+            name,
+        }
+    }
+
+    pub fn write(&mut self, display: &impl Facade, data: &[T]) {
+        if data.len() > self.buffer.len() {
+            *self = StreamBuffer::new(display, data.len().next_power_of_two(), self.name);
+        }
+        if !data.is_empty() {
+            self.buffer.slice_mut(0..data.len()).unwrap().write(data);
+        }
+        self.len = data.len();
+    }
+
+    // Part of what the last `write` wrote
+    pub fn slice(&self, range: Range<usize>) -> VertexBufferSlice<'_, T> {
+        assert!(
+            range.end <= self.len,
+            "{} were not written this frame",
+            self.name
+        );
+        self.buffer.slice(range).unwrap()
+    }
+}
+
+// GPU resources that live as long as the renderer, so drawing a frame doesn't
+// create and drop buffers. The default textures are owned by `Assets`.
+pub struct RenderCache {
+    // Overlays and lines of a frame, all written at once
+    pub overlays: StreamBuffer<Vertex>,
+    pub lines: StreamBuffer<LineVertex>,
+    // Placement of everything drawn in a frame, drawn with instancing
+    pub instances: StreamBuffer<Instance>,
+    pub shadow_map: ShadowMap,
+}
+
+impl RenderCache {
+    pub fn new(display: &impl Facade) -> Self {
+        RenderCache {
+            overlays: StreamBuffer::new(display, 1024, "overlays"),
+            lines: StreamBuffer::new(display, 256, "lines"),
+            instances: StreamBuffer::new(display, 256, "instances"),
+            shadow_map: ShadowMap::new(display, ShadowSettings::default()),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use nalgebra_glm::{Mat4, Vec3};
+
+use crate::{assets::MeshId, camera::Camera, light::Light, mesh::PartPose, vertex::Vertex};
+
+// What game systems draw with. Everything submitted during a frame is drawn
+// when the frame is presented, so implementations are free to batch it.
+pub trait Renderer {
+    // Mesh placed by `model`, with its parts posed by `pose`
+    fn submit_mesh(&mut self, mesh: &MeshId, model: Mat4, pose: Option<&PartPose>);
+
+    // Unlit triangle strip of one color, like a selection ring on the ground
+    fn submit_overlay(&mut self, strip: &[Vertex], model: Mat4, color: Vec3);
+
+    // Lines between pairs of world space points, drawn on top of everything
+    fn submit_lines(&mut self, lines: &[[Vec3; 2]], color: Vec3);
+
+    // Draw everything submitted since the last present as seen by `camera`
+    fn present(&mut self, camera: &Camera, lights: &[&Light]);
+}
+
+// Something submitted to a `RecordingRenderer`
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Mesh {
+        mesh: MeshId,
+        model: Mat4,
+        pose: Option<PartPose>,
+    },
+    Overlay {
+        strip: Vec<Vertex>,
+        model: Mat4,
+        color: Vec3,
+    },
+    Lines {
+        lines: Vec<[Vec3; 2]>,
+        color: Vec3,
+    },
+    Present {
+        view: Mat4,
+        projection: Mat4,
+        lights: usize,
+    },
+}
+
+// Renderer that draws nothing and keeps the list of everything submitted, so
+// systems can run and what they draw can be checked without a GPU
+#[derive(Default)]
+pub struct RecordingRenderer {
+    pub commands: Vec<DrawCommand>,
+}
+
+impl Renderer for RecordingRenderer {
+    fn submit_mesh(&mut self, mesh: &MeshId, model: Mat4, pose: Option<&PartPose>) {
+        self.commands.push(DrawCommand::Mesh {
+            mesh: mesh.clone(),
+            model,
+            pose: pose.cloned(),
+        });
+    }
+
+    fn submit_overlay(&mut self, strip: &[Vertex], model: Mat4, color: Vec3) {
+        self.commands.push(DrawCommand::Overlay {
+            strip: strip.to_vec(),
+            model,
+            color,
+        });
+    }
+
+    fn submit_lines(&mut self, lines: &[[Vec3; 2]], color: Vec3) {
+        self.commands.push(DrawCommand::Lines {
+            lines: lines.to_vec(),
+            color,
+        });
+    }
+
+    fn present(&mut self, camera: &Camera, lights: &[&Light]) {
+        self.commands.push(DrawCommand::Present {
+            view: camera.view,
+            projection: camera.projection,
+            lights: lights.len(),
+        });
+    }
+}
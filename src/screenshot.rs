@@ -0,0 +1,137 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use glium::{
+    backend::Facade,
+    framebuffer::{
+        DepthRenderBuffer, RenderBufferCreationError, SimpleFrameBuffer, ValidationError,
+    },
+    texture::{
+        DepthFormat, MipmapsOption, RawImage2d, SrgbFormat, SrgbTexture2d, TextureCreationError,
+    },
+};
+use image::{ImageError, RgbaImage};
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    Texture(TextureCreationError),
+    DepthBuffer(RenderBufferCreationError),
+    Framebuffer(ValidationError),
+    Image(ImageError),
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::Texture(err) => write!(f, "failed to create color texture: {}", err),
+            ScreenshotError::DepthBuffer(err) => {
+                write!(f, "failed to create depth buffer: {}", err)
+            }
+            ScreenshotError::Framebuffer(err) => write!(f, "failed to create framebuffer: {}", err),
+            ScreenshotError::Image(err) => write!(f, "failed to save image: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScreenshotError::Texture(err) => Some(err),
+            ScreenshotError::DepthBuffer(err) => Some(err),
+            ScreenshotError::Framebuffer(err) => Some(err),
+            ScreenshotError::Image(err) => Some(err),
+        }
+    }
+}
+
+impl From<TextureCreationError> for ScreenshotError {
+    fn from(err: TextureCreationError) -> Self {
+        ScreenshotError::Texture(err)
+    }
+}
+
+impl From<RenderBufferCreationError> for ScreenshotError {
+    fn from(err: RenderBufferCreationError) -> Self {
+        ScreenshotError::DepthBuffer(err)
+    }
+}
+
+impl From<ValidationError> for ScreenshotError {
+    fn from(err: ValidationError) -> Self {
+        ScreenshotError::Framebuffer(err)
+    }
+}
+
+impl From<ImageError> for ScreenshotError {
+    fn from(err: ImageError) -> Self {
+        ScreenshotError::Image(err)
+    }
+}
+
+// Color and depth buffer to render into instead of the window, of any size.
+// The color is stored in sRGB like the window's, so images look the same.
+pub struct Offscreen {
+    color: SrgbTexture2d,
+    depth: DepthRenderBuffer,
+}
+
+impl Offscreen {
+    pub fn new(facade: &impl Facade, width: u32, height: u32) -> Result<Self, ScreenshotError> {
+        Ok(Offscreen {
+            color: SrgbTexture2d::empty_with_format(
+                facade,
+                SrgbFormat::U8U8U8U8,
+                MipmapsOption::NoMipmap,
+                width,
+                height,
+            )?,
+            depth: DepthRenderBuffer::new(facade, DepthFormat::I24, width, height)?,
+        })
+    }
+
+    pub fn framebuffer(
+        &self,
+        facade: &impl Facade,
+    ) -> Result<SimpleFrameBuffer<'_>, ScreenshotError> {
+        Ok(SimpleFrameBuffer::with_depth_buffer(
+            facade,
+            &self.color,
+            &self.depth,
+        )?)
+    }
+
+    // Read back what was rendered
+    pub fn read(&self) -> RgbaImage {
+        let pixels: RawImage2d<u8> = self.color.read();
+        let mut image = RgbaImage::from_raw(pixels.width, pixels.height, pixels.data.into_owned())
+            .expect("Texture data doesn't match its size");
+        // OpenGL returns the bottom row first
+        image::imageops::flip_vertical_in_place(&mut image);
+        image
+    }
+}
+
+// Render a single frame of the given size offscreen and save it as PNG
+pub fn capture(
+    facade: &impl Facade,
+    width: u32,
+    height: u32,
+    path: &Path,
+    render: impl FnOnce(&mut SimpleFrameBuffer),
+) -> Result<(), ScreenshotError> {
+    let offscreen = Offscreen::new(facade, width, height)?;
+    render(&mut offscreen.framebuffer(facade)?);
+    offscreen.read().save(path)?;
+    Ok(())
+}
+
+// File name for a screenshot taken now, like "screenshot-1700000000.png"
+pub fn screenshot_path() -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    PathBuf::from(format!("screenshot-{}.png", seconds))
+}
@@ -1,6 +1,6 @@
 use hecs::{World, Entity};
 
-use crate::{bounding_circle::BoundingCircle, mouse::Cursor, transformation::Transformation};
+use crate::{assets::Assets, bounding_circle::BoundingCircle, mouse::Cursor, picking::pick};
 
 #[derive(Debug, Clone)]
 pub struct Selectable {
@@ -19,26 +19,18 @@ impl Selectable {
     }
 }
 
-pub fn select_system(world: &mut World, cursor_entity: Entity) {
-    // let mut cursors = world.query::<(&Cursor,)>();
-    // let cursor = cursors.iter().last();
-    //
-    let cursor = world.get::<&Cursor>(cursor_entity);
-    if let Ok(cursor) = cursor {
-        world
-            .query::<(&mut Selectable, &Transformation)>()
-            .iter()
-            .for_each(|(_, (selectable, transformation))| {
-                let cursor_pos = cursor.position.xz();
-                let bc_pos = selectable.bounding_circle.ground_pos + transformation.pos.xz();
-                let world_r = selectable.bounding_circle.r * transformation.scale;
+// Hover the selectable unit under the cursor, the closest one the ray from
+// the camera through the cursor hits
+pub fn select_system(world: &mut World, assets: &Assets, cursor_entity: Entity) {
+    let ray = world
+        .get::<&Cursor>(cursor_entity)
+        .ok()
+        .and_then(|cursor| cursor.ray);
+    let hovered = ray
+        .and_then(|ray| pick(world, assets, &ray))
+        .map(|hit| hit.entity);
 
-                let diff = bc_pos - cursor_pos;
-                if diff.norm() < world_r {
-                    selectable.hover = true;
-                } else {
-                    selectable.hover = false;
-                }
-            });
+    for (id, (selectable,)) in world.query_mut::<(&mut Selectable,)>() {
+        selectable.hover = Some(id) == hovered;
     }
 }
@@ -1,16 +1,44 @@
-use std::{path::PathBuf, fs::File, io::Read};
+use std::{fmt, fs, io, path::PathBuf};
 
-use glium::{Program, Display};
+use glium::{backend::Facade, program::ProgramCreationError, Program};
 
-pub fn load(display: &Display, fragment: PathBuf, vertex: PathBuf) -> Program {
-    let mut v_file = File::open(vertex).unwrap();
-    let mut f_file = File::open(fragment).unwrap();
+#[derive(Debug)]
+pub enum ShaderError {
+    Io { path: PathBuf, source: io::Error },
+    Compile(ProgramCreationError),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Io { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            ShaderError::Compile(err) => write!(f, "failed to compile shaders: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShaderError::Io { source, .. } => Some(source),
+            ShaderError::Compile(err) => Some(err),
+        }
+    }
+}
+
+fn read(path: PathBuf) -> Result<String, ShaderError> {
+    fs::read_to_string(&path).map_err(|source| ShaderError::Io { path, source })
+}
 
-    let mut v_src = String::new();
-    let mut f_src = String::new();
-    
-    v_file.read_to_string(&mut v_src).unwrap();
-    f_file.read_to_string(&mut f_src).unwrap();
+pub fn load(
+    display: &impl Facade,
+    fragment: PathBuf,
+    vertex: PathBuf,
+) -> Result<Program, ShaderError> {
+    let v_src = read(vertex)?;
+    let f_src = read(fragment)?;
 
-    Program::from_source(display, &v_src, &f_src, None).expect("Failed to compile shaders")
+    Program::from_source(display, &v_src, &f_src, None).map_err(ShaderError::Compile)
 }
@@ -0,0 +1,209 @@
+use glium::{
+    backend::Facade,
+    framebuffer::SimpleFrameBuffer,
+    texture::DepthTexture2d,
+    uniforms::{
+        DepthTextureComparison, MagnifySamplerFilter, MinifySamplerFilter, Sampler,
+        SamplerWrapFunction,
+    },
+};
+use nalgebra_glm::{look_at, ortho, vec3, vec4, Mat4, Vec3};
+
+use crate::camera::Camera;
+
+// How far above the ground shadow casters reach, like the top of a unit
+const CASTER_HEIGHT: f32 = 5.0;
+// How far towards the light shadow casters are drawn, so things that are
+// off screen still cast shadows on screen
+const LIGHT_DISTANCE: f32 = 100.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    // Width and height of the shadow map in texels
+    pub resolution: u32,
+    // Distance from the middle of the visible ground the shadow map covers, in
+    // world units. It doesn't follow the zoom, so a texel always covers the
+    // same area and shadow edges stay put while the camera moves.
+    pub radius: f32,
+    // Depth offset against shadow acne, in shadow map depth that goes from 0
+    // to 1 over the light's depth range. Surfaces at a grazing angle to the
+    // light get up to ten times this.
+    pub bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            enabled: true,
+            resolution: 2048,
+            radius: 40.0,
+            bias: 0.0005,
+        }
+    }
+}
+
+// Depth of the scene as seen from the sun. It is rendered before the main
+// pass, which compares against it to find what is in shadow.
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    texture: DepthTexture2d,
+}
+
+impl ShadowMap {
+    pub fn new(display: &impl Facade, settings: ShadowSettings) -> Self {
+        ShadowMap {
+            settings,
+            texture: create_texture(display, settings.resolution),
+        }
+    }
+
+    // Framebuffer of the shadow pass, resized first when the resolution changed
+    pub fn framebuffer(&mut self, display: &impl Facade) -> SimpleFrameBuffer<'_> {
+        if self.texture.width() != self.settings.resolution {
+            self.texture = create_texture(display, self.settings.resolution);
+        }
+        SimpleFrameBuffer::depth_only(display, &self.texture)
+            .expect("Failed to create shadow map framebuffer")
+    }
+
+    // Sampler for a `sampler2DShadow`. Linear filtering blends the depth
+    // comparisons of neighbouring texels, on top of the PCF in the shader.
+    pub fn sampled(&self) -> Sampler<'_, DepthTexture2d> {
+        Sampler::new(&self.texture)
+            .wrap_function(SamplerWrapFunction::Clamp)
+            .minify_filter(MinifySamplerFilter::Linear)
+            .magnify_filter(MagnifySamplerFilter::Linear)
+            .depth_texture_comparison(Some(DepthTextureComparison::LessOrEqual))
+    }
+}
+
+fn create_texture(display: &impl Facade, resolution: u32) -> DepthTexture2d {
+    DepthTexture2d::empty(display, resolution, resolution)
+        .expect("Failed to create shadow map texture")
+}
+
+// Corners of the ground the camera sees. Rays of the frustum corners that
+// don't hit the ground within the far plane end straight below it.
+fn visible_ground(camera: &Camera) -> [Vec3; 4] {
+    let inverse = (camera.projection * camera.view)
+        .try_inverse()
+        .unwrap_or_else(Mat4::identity);
+    let unproject = |x: f32, y: f32, z: f32| {
+        let point = inverse * vec4(x, y, z, 1.0);
+        point.xyz() / point.w
+    };
+
+    [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| {
+        let near = unproject(x, y, -1.0);
+        let far = unproject(x, y, 1.0);
+        if near.y > 0.0 && far.y < 0.0 {
+            near + (far - near) * (near.y / (near.y - far.y))
+        } else {
+            vec3(far.x, 0.0, far.z)
+        }
+    })
+}
+
+// View projection of a directional light shining in `direction`, covering the
+// ground within `settings.radius` of the middle of what the camera can see
+pub fn light_space(direction: Vec3, camera: &Camera, settings: &ShadowSettings) -> Mat4 {
+    let up = if direction.x.abs() < 1e-3 && direction.z.abs() < 1e-3 {
+        Vec3::z()
+    } else {
+        Vec3::y()
+    };
+    // The view only rotates, so the covered area moves over fixed texels
+    let view = look_at(&-direction, &Vec3::zeros(), &up);
+
+    let mut min = vec3(f32::MAX, f32::MAX, f32::MAX);
+    let mut max = vec3(f32::MIN, f32::MIN, f32::MIN);
+    for corner in visible_ground(camera) {
+        for height in [0.0, CASTER_HEIGHT] {
+            let point = (view * (corner + Vec3::y() * height).push(1.0)).xyz();
+            min = min.inf(&point);
+            max = max.sup(&point);
+        }
+    }
+
+    // Move the covered area in whole texels of a fixed size, so shadow edges
+    // don't shimmer while the camera moves
+    let texel = 2.0 * settings.radius / settings.resolution as f32;
+    let snap = |v: f32| (v / texel).round() * texel;
+    let center_x = snap((min.x + max.x) / 2.0);
+    let center_y = snap((min.y + max.y) / 2.0);
+
+    // The view looks down -z, so near and far are the negated z bounds
+    let projection = ortho(
+        center_x - settings.radius,
+        center_x + settings.radius,
+        center_y - settings.radius,
+        center_y + settings.radius,
+        -max.z - LIGHT_DISTANCE,
+        -min.z + 1.0,
+    );
+    projection * view
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec2;
+
+    use super::*;
+    use crate::camera::CameraRig;
+
+    const SUN: Vec3 = Vec3::new(5.0, -5.0, -5.0);
+
+    fn camera_at(focus: Vec3, distance: f32) -> Camera {
+        let rig = CameraRig::new(
+            focus,
+            std::f32::consts::FRAC_PI_4,
+            distance,
+            Vec2::repeat(-100.0),
+            Vec2::repeat(100.0),
+        );
+        let mut camera = Camera::new(0.1, 50.0, 800, 600);
+        rig.apply(&mut camera);
+        camera
+    }
+
+    #[test]
+    fn texels_keep_their_size_and_place() {
+        let settings = ShadowSettings::default();
+        let texel = 2.0 * settings.radius / settings.resolution as f32;
+        let first = light_space(SUN, &camera_at(Vec3::zeros(), 20.0), &settings);
+        for (focus, distance) in [
+            (vec3(0.013, 0.0, 0.007), 20.0),
+            (vec3(3.3, 0.0, -1.7), 40.0),
+        ] {
+            let moved = light_space(SUN, &camera_at(focus, distance), &settings);
+            // Same texel size whatever the zoom, only the depth range follows it
+            let scale = moved.fixed_view::<2, 3>(0, 0) - first.fixed_view::<2, 3>(0, 0);
+            assert!(scale.norm() < 1e-6);
+            // Shifted by whole texels, the shadow map goes from -1 to 1
+            for row in 0..2 {
+                let shift = (moved[(row, 3)] - first[(row, 3)]) * settings.radius / texel;
+                assert!(
+                    (shift - shift.round()).abs() < 1e-2,
+                    "shifted by {} texels",
+                    shift
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn covers_the_visible_ground_when_zoomed_out() {
+        let settings = ShadowSettings::default();
+        let camera = camera_at(Vec3::zeros(), 40.0);
+        let light_space = light_space(SUN, &camera, &settings);
+        for corner in visible_ground(&camera) {
+            let point = light_space * corner.push(1.0);
+            assert!(
+                point.x.abs() <= 1.0 && point.y.abs() <= 1.0,
+                "{:?} is not covered",
+                corner
+            );
+        }
+    }
+}
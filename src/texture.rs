@@ -1,30 +1,214 @@
-use std::path::PathBuf;
-use image::{io::Reader as ImageReader, Rgb};
+use std::{fmt, path::Path};
 
-pub fn sample_texture() -> Vec<Vec<(f32, f32, f32)>> {
-    let mut texture = vec![];
-    for _ in 0..100 {
-        let mut row = vec![];
-        for x in 0..100 {
-            row.push(((x as f32) / 255.0, 0.0, 0.0));
+use glium::{
+    backend::Facade,
+    texture::{MipmapsOption, RawImage2d, SrgbTexture2d, Texture2d, TextureCreationError},
+    uniforms::{
+        AsUniformValue, MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerBehavior,
+        SamplerWrapFunction, UniformValue,
+    },
+};
+use image::{ImageError, Rgba, RgbaImage};
+
+use crate::material::TextureSource;
+
+#[derive(Debug)]
+pub enum TextureError {
+    Image(ImageError),
+    Upload(TextureCreationError),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Image(err) => write!(f, "failed to read image: {}", err),
+            TextureError::Upload(err) => write!(f, "failed to create texture: {}", err),
         }
-        texture.push(row);
     }
-    texture
 }
 
-pub fn load_from(path: PathBuf) -> Vec<Vec<(f32, f32, f32)>> {
-    let image = ImageReader::open(path).unwrap().decode().unwrap();
-    let f32_image = image.to_rgb32f();
-    let mut texture = vec![]; 
-    f32_image.rows().for_each(|row| {
-        let mut texture_row = vec![];
-        row.for_each(|Rgb(pixel)| {
-            texture_row.push((pixel[0], pixel[1], pixel[2]));
-        });
-        texture.push(texture_row);
-    });
-    texture
+impl std::error::Error for TextureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TextureError::Image(err) => Some(err),
+            TextureError::Upload(err) => Some(err),
+        }
+    }
 }
 
+impl From<ImageError> for TextureError {
+    fn from(err: ImageError) -> Self {
+        TextureError::Image(err)
+    }
+}
+
+impl From<TextureCreationError> for TextureError {
+    fn from(err: TextureCreationError) -> Self {
+        TextureError::Upload(err)
+    }
+}
+
+// How the texels of an image are meant. Colors are stored in sRGB and turned
+// linear when sampled, data like normal maps is used as it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+// How a texture is stored and sampled. Mipmaps are generated when the minify
+// filter uses them.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureOptions {
+    pub wrap: SamplerWrapFunction,
+    pub minify: MinifySamplerFilter,
+    pub magnify: MagnifySamplerFilter,
+    pub color_space: ColorSpace,
+}
 
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            wrap: SamplerWrapFunction::Repeat,
+            minify: MinifySamplerFilter::LinearMipmapLinear,
+            magnify: MagnifySamplerFilter::Linear,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+impl TextureOptions {
+    fn mipmaps(&self) -> MipmapsOption {
+        match self.minify {
+            MinifySamplerFilter::Nearest | MinifySamplerFilter::Linear => MipmapsOption::NoMipmap,
+            _ => MipmapsOption::AutoGeneratedMipmaps,
+        }
+    }
+
+    fn sampler_behavior(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (self.wrap, self.wrap, self.wrap),
+            minify_filter: self.minify,
+            magnify_filter: self.magnify,
+            ..Default::default()
+        }
+    }
+}
+
+pub enum TextureStorage {
+    Srgb(SrgbTexture2d),
+    Linear(Texture2d),
+}
+
+// Texture in the color space of its options, along with how it should be sampled
+pub struct Texture {
+    pub texture: TextureStorage,
+    pub options: TextureOptions,
+}
+
+// Sampler of either kind of texture, both are a `sampler2D` in the shaders
+pub enum TextureSampler<'a> {
+    Srgb(Sampler<'a, SrgbTexture2d>),
+    Linear(Sampler<'a, Texture2d>),
+}
+
+impl AsUniformValue for TextureSampler<'_> {
+    fn as_uniform_value(&self) -> UniformValue<'_> {
+        match self {
+            TextureSampler::Srgb(sampler) => sampler.as_uniform_value(),
+            TextureSampler::Linear(sampler) => sampler.as_uniform_value(),
+        }
+    }
+}
+
+impl Texture {
+    pub fn from_image(
+        display: &impl Facade,
+        image: &RgbaImage,
+        options: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        let texture = match options.color_space {
+            ColorSpace::Srgb => TextureStorage::Srgb(SrgbTexture2d::with_mipmaps(
+                display,
+                raw_image(image),
+                options.mipmaps(),
+            )?),
+            ColorSpace::Linear => TextureStorage::Linear(Texture2d::with_mipmaps(
+                display,
+                raw_image(image),
+                options.mipmaps(),
+            )?),
+        };
+        Ok(Texture { texture, options })
+    }
+
+    pub fn load(
+        display: &impl Facade,
+        source: &TextureSource,
+        options: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        match source {
+            TextureSource::File(path) => Texture::from_image(display, &load_image(path)?, options),
+            TextureSource::Image(image) => Texture::from_image(display, image, options),
+        }
+    }
+
+    // Single texel of one color, as a stand-in for a missing texture
+    pub fn solid(display: &impl Facade, color: [u8; 4], color_space: ColorSpace) -> Self {
+        let image = RgbaImage::from_pixel(1, 1, Rgba(color));
+        let options = TextureOptions {
+            minify: MinifySamplerFilter::Nearest,
+            magnify: MagnifySamplerFilter::Nearest,
+            color_space,
+            ..Default::default()
+        };
+        Texture::from_image(display, &image, options).expect("Failed to create texture")
+    }
+
+    pub fn sampled(&self) -> TextureSampler<'_> {
+        let behavior = self.options.sampler_behavior();
+        match &self.texture {
+            TextureStorage::Srgb(texture) => TextureSampler::Srgb(Sampler(texture, behavior)),
+            TextureStorage::Linear(texture) => TextureSampler::Linear(Sampler(texture, behavior)),
+        }
+    }
+}
+
+// Rows are uploaded top first, matching the texture coordinates of the loaders
+fn raw_image(image: &RgbaImage) -> RawImage2d<'static, u8> {
+    RawImage2d::from_raw_rgba(image.as_raw().clone(), image.dimensions())
+}
+
+pub fn load_image(path: &Path) -> Result<RgbaImage, ImageError> {
+    Ok(image::open(path)?.into_rgba8())
+}
+
+pub fn sample_image() -> RgbaImage {
+    RgbaImage::from_fn(100, 100, |x, _| Rgba([x as u8, 0, 0, 255]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless;
+
+    #[test]
+    fn textures_are_stored_in_their_color_space() {
+        let context = headless::create_context().unwrap();
+        let color = Texture::solid(&context, [255, 0, 0, 255], ColorSpace::Srgb);
+        assert!(matches!(color.texture, TextureStorage::Srgb(_)));
+
+        let image = RgbaImage::from_pixel(2, 2, Rgba([128, 128, 255, 255]));
+        let options = TextureOptions {
+            color_space: ColorSpace::Linear,
+            ..Default::default()
+        };
+        let normals = Texture::from_image(&context, &image, options).unwrap();
+        let TextureStorage::Linear(texture) = &normals.texture else {
+            panic!("normal map was stored as sRGB");
+        };
+        // Linear texels are stored as they are
+        let texels: Vec<Vec<(u8, u8, u8, u8)>> = texture.read();
+        assert_eq!(texels[0][0], (128, 128, 255, 255));
+    }
+}
@@ -23,15 +23,15 @@ impl Transformation {
 
     pub fn new(pos: Vec3, rot: f32, scale: f32) -> Self {
         Transformation {
-            pos: pos,
+            pos,
             rotation: rot,
-            scale: scale,
+            scale,
         }
     }
 
     pub fn translation(pos: Vec3) -> Self {
         Transformation {
-            pos: pos,
+            pos,
             rotation: 0.0,
             scale: 1.0,
         }
@@ -1,11 +1,113 @@
+use std::collections::HashMap;
+
 use glium::implement_vertex;
+use nalgebra_glm::{Mat4, Vec3};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
-    pub texture_coord: [f32; 2]
+    pub texture_coord: [f32; 2],
+}
+
+implement_vertex!(Vertex, position, normal, texture_coord);
+
+// Vertex of the unlit, colored lines drawn on top of the scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+implement_vertex!(LineVertex, position, color);
+
+// Per-instance attributes of instanced draws: where one copy of a mesh is
+// placed and what color it is tinted with
+#[derive(Clone, Copy)]
+pub struct Instance {
+    pub model: [[f32; 4]; 4],
+    pub tint: [f32; 3],
+}
+
+implement_vertex!(Instance, model, tint);
+
+impl Instance {
+    pub fn new(model: Mat4, tint: Vec3) -> Self {
+        Instance {
+            model: model.into(),
+            tint: tint.into(),
+        }
+    }
+}
+
+// Merge identical vertices of a triangle list into a compact vertex list and
+// the indices that rebuild the original list from it
+pub fn deduplicate(vertices: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique: Vec<Vertex> = vec![];
+    let mut indices = Vec::with_capacity(vertices.len());
+    let mut seen: HashMap<[u32; 8], u32> = HashMap::new();
+
+    for v in vertices {
+        let mut key = [0; 8];
+        let attributes = v.position.iter().chain(&v.normal).chain(&v.texture_coord);
+        for (k, attribute) in key.iter_mut().zip(attributes) {
+            *k = attribute.to_bits();
+        }
+
+        let index = *seen.entry(key).or_insert_with(|| {
+            unique.push(*v);
+            (unique.len() - 1) as u32
+        });
+        indices.push(index);
+    }
+
+    (unique, indices)
 }
 
-implement_vertex!(Vertex, position, normal, texture_coord );
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn vertex(position: [f32; 3], normal: [f32; 3]) -> Vertex {
+        Vertex {
+            position,
+            normal,
+            texture_coord: [0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn deduplicate_merges_identical_vertices() {
+        let a = vertex([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let b = vertex([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let c = vertex([1.0, 0.0, 1.0], [0.0, 1.0, 0.0]);
+        let d = vertex([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]);
+
+        let (unique, indices) = deduplicate(&[a, b, c, a, c, d]);
+        assert_eq!(unique, [a, b, c, d]);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn deduplicate_keeps_vertices_differing_in_any_attribute() {
+        let a = vertex([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let other_normal = vertex([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let other_uv = Vertex {
+            texture_coord: [0.5, 0.0],
+            ..a
+        };
+        // Equal as floats, but not the same bits
+        let negative_zero = vertex([-0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+
+        let (unique, indices) = deduplicate(&[a, other_normal, other_uv, negative_zero, a]);
+        assert_eq!(unique.len(), 4);
+        assert_eq!(indices, [0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn deduplicate_empty() {
+        let (unique, indices) = deduplicate(&[]);
+        assert!(unique.is_empty());
+        assert!(indices.is_empty());
+    }
+}
@@ -1,118 +1,716 @@
 use std::{
+    fmt,
     fs::File,
-    io::{BufRead, BufReader, Cursor, Read},
-    path::PathBuf,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use crate::vertex::Vertex;
+use crate::{
+    material::{self, Material},
+    mesh_data::{Bounds, MeshData, PartData, SubMeshData},
+    normals,
+    vertex::{self, Vertex},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    UnknownCommand {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    MissingArgument {
+        line: usize,
+        column: usize,
+        command: String,
+    },
+    InvalidNumber {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    InvalidIndex {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    UnknownMaterial {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    MaterialLibrary {
+        line: usize,
+        column: usize,
+        path: PathBuf,
+        source: Box<ObjError>,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "failed to read file: {}", err),
+            ObjError::UnknownCommand {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: unrecognized command '{}'", line, column, token),
+            ObjError::MissingArgument {
+                line,
+                column,
+                command,
+            } => write!(f, "{}:{}: missing argument for '{}'", line, column, command),
+            ObjError::InvalidNumber {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: invalid number '{}'", line, column, token),
+            ObjError::InvalidIndex {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: invalid index '{}'", line, column, token),
+            ObjError::UnknownMaterial {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: unknown material '{}'", line, column, token),
+            ObjError::MaterialLibrary {
+                line,
+                column,
+                path,
+                source,
+            } => write!(
+                f,
+                "{}:{}: in material library {}: {}",
+                line,
+                column,
+                path.display(),
+                source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io(err) => Some(err),
+            ObjError::MaterialLibrary { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(err: io::Error) -> Self {
+        ObjError::Io(err)
+    }
+}
+
+// Whitespace separated tokens of a single line, remembering where each one
+// starts so errors can point at the offending token (1-based line and column).
+pub(crate) struct Tokens<'a> {
+    line: &'a str,
+    line_number: usize,
+    offset: usize,
+    command: &'a str,
+    command_start: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(line: &'a str, line_number: usize) -> Self {
+        Tokens {
+            line,
+            line_number,
+            offset: 0,
+            command: "",
+            command_start: 0,
+        }
+    }
+
+    fn next_token(&mut self) -> Option<(usize, &'a str)> {
+        let rest = &self.line[self.offset..];
+        let start = self.offset + (rest.len() - rest.trim_start().len());
+        let rest = &self.line[start..];
+        if rest.is_empty() {
+            return None;
+        }
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        self.offset = start + len;
+        Some((start, &rest[..len]))
+    }
+
+    fn column(&self, byte_offset: usize) -> usize {
+        self.line[..byte_offset].chars().count() + 1
+    }
+
+    pub(crate) fn command(&mut self) -> Option<&'a str> {
+        let (start, command) = self.next_token()?;
+        self.command = command;
+        self.command_start = start;
+        Some(command)
+    }
+
+    pub(crate) fn unknown_command(&self) -> ObjError {
+        ObjError::UnknownCommand {
+            line: self.line_number,
+            column: self.column(self.command_start),
+            token: self.command.to_string(),
+        }
+    }
+
+    pub(crate) fn argument(&mut self) -> Result<(usize, &'a str), ObjError> {
+        match self.optional_argument() {
+            Some(argument) => Ok(argument),
+            None => Err(ObjError::MissingArgument {
+                line: self.line_number,
+                column: self.column(self.offset),
+                command: self.command.to_string(),
+            }),
+        }
+    }
+
+    pub(crate) fn optional_argument(&mut self) -> Option<(usize, &'a str)> {
+        self.next_token()
+            .map(|(start, token)| (self.column(start), token))
+    }
+
+    pub(crate) fn parse_number<T: FromStr>(
+        &self,
+        column: usize,
+        token: &str,
+    ) -> Result<T, ObjError> {
+        token.parse().map_err(|_| ObjError::InvalidNumber {
+            line: self.line_number,
+            column,
+            token: token.to_string(),
+        })
+    }
+
+    pub(crate) fn number<T: FromStr>(&mut self) -> Result<T, ObjError> {
+        let (column, token) = self.argument()?;
+        self.parse_number(column, token)
+    }
+
+    pub(crate) fn optional_number<T: FromStr>(&mut self) -> Result<Option<T>, ObjError> {
+        self.optional_argument()
+            .map(|(column, token)| self.parse_number(column, token))
+            .transpose()
+    }
+
+    pub(crate) fn invalid_index(&self, column: usize, token: &str) -> ObjError {
+        ObjError::InvalidIndex {
+            line: self.line_number,
+            column,
+            token: token.to_string(),
+        }
+    }
+}
+
+// Resolve a 1-based .obj index into a 0-based index into a list of `len` elements.
+// Negative indices are relative to the end of the list, so -1 is the last element.
+fn resolve_index(index: &str, len: usize) -> Option<usize> {
+    let index: isize = index.parse().ok()?;
+    let resolved = match index {
+        0 => return None,
+        i if i > 0 => i as usize - 1,
+        i => len.checked_sub(i.unsigned_abs())?,
+    };
+    (resolved < len).then_some(resolved)
+}
+
+// A single corner of a face, `v`, `v/vt`, `v//vn` or `v/vt/vn`
+#[derive(Clone, Copy)]
+struct FaceVertex {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+struct Triangle {
+    corners: [FaceVertex; 3],
+    smoothing_group: u32,
+    part: usize,
+    material: Option<usize>,
+}
+
+// Index of the part with the given name, adding it if it doesn't exist yet
+fn find_part(parts: &mut Vec<PartData>, name: &str) -> usize {
+    parts
+        .iter()
+        .position(|part| part.name == name)
+        .unwrap_or_else(|| {
+            parts.push(PartData {
+                name: name.to_string(),
+                parent: None,
+                submeshes: vec![],
+            });
+            parts.len() - 1
+        })
+}
 
-pub fn load(path: PathBuf) -> (Vec<Vertex>, Vec<u32>) {
-    let mut file = File::open(path).expect("Could not open .obj file.");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Failed to read .obj file.");
-    let mut stream = BufReader::new(Cursor::new(contents));
+pub fn load(path: PathBuf) -> Result<MeshData, ObjError> {
+    load_with_crease_angle(path, normals::DEFAULT_CREASE_ANGLE)
+}
+
+// Load an .obj file, generating normals for faces that don't specify any.
+// Generated normals are smoothed within the `s` smoothing groups of the file,
+// but not across edges sharper than `crease_angle` (radians).
+pub fn load_with_crease_angle(path: PathBuf, crease_angle: f32) -> Result<MeshData, ObjError> {
+    let stream = BufReader::new(File::open(&path)?);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse(stream, dir, crease_angle)
+}
+
+// Parse .obj data, material libraries are looked up relative to `dir`
+fn parse(stream: impl BufRead, dir: &Path, crease_angle: f32) -> Result<MeshData, ObjError> {
     let mut v_data = vec![];
-    let mut i_data = vec![];
-    let mut ni_data = vec![];
-    let mut ti_data = vec![];
     let mut vn_data = vec![];
     let mut vt_data = vec![];
+    let mut triangles: Vec<Triangle> = vec![];
+    let mut smoothing_group = 0;
+    let mut materials: Vec<Material> = vec![];
+    let mut material = None;
+    let mut dependencies = vec![];
+    // Faces before any `o` or `g` go to an unnamed part
+    let mut parts: Vec<PartData> = vec![];
+    let mut object = String::new();
+    let mut part = None;
+
+    for (line_idx, line) in stream.lines().enumerate() {
+        let line = line?;
+        let mut tokens = Tokens::new(&line, line_idx + 1);
 
-    let mut current_line = String::new();
-    while stream
-        .read_line(&mut current_line)
-        .expect("Malformed line in obj file")
-        != 0
-    {
         // Skip empty lines and comments
-        if current_line.is_empty()
-            || current_line == "\n"
-            || current_line.chars().next().unwrap() == '#'
-        {
-            current_line.clear();
-            continue;
-        }
+        let command = match tokens.command() {
+            Some(command) if !command.starts_with('#') => command,
+            _ => continue,
+        };
 
-        let mut split = current_line.strip_suffix("\n").unwrap().split(' ');
-        let command = split.next().unwrap();
         match command {
-            "o" => {}
+            "o" => {
+                let (_, name) = tokens.argument()?;
+                object = name.to_string();
+                part = None;
+            }
+            "g" => {
+                // A face may belong to several groups, the first one names the part.
+                // A `g` without names returns to the enclosing object.
+                part = tokens
+                    .optional_argument()
+                    .map(|(_, name)| find_part(&mut parts, name));
+            }
             "v" => {
-                let x: f32 = split.next().unwrap().parse().unwrap();
-                let y: f32 = split.next().unwrap().parse().unwrap();
-                let z: f32 = split.next().unwrap().parse().unwrap();
-                v_data.push((x, y, z));
+                let x: f32 = tokens.number()?;
+                let y: f32 = tokens.number()?;
+                let z: f32 = tokens.number()?;
+                v_data.push([x, y, z]);
             }
             "f" => {
-                // Assume triangulated faces
-                for _ in 0..3 {
-                    let index_str = split.next().expect("malformed index string");
-                    let mut split = index_str.split("/");
-                    let vi: u32 = split
-                        .next()
-                        .expect("Expected vertex index")
-                        .parse()
-                        .unwrap();
-                    let ti: u32 = split
-                        .next()
-                        .expect("Expected vertex texture coord index")
-                        .parse()
-                        .unwrap();
-                    let ni: u32 = split
+                let mut face = vec![];
+                loop {
+                    // A face needs at least three corners, anything after that is optional
+                    let (column, index_str) = if face.len() < 3 {
+                        tokens.argument()?
+                    } else {
+                        match tokens.optional_argument() {
+                            Some(argument) => argument,
+                            None => break,
+                        }
+                    };
+                    let invalid = || tokens.invalid_index(column, index_str);
+                    let mut split = index_str.split('/');
+
+                    let v = split
                         .next()
-                        .expect("Expected vertex normal index")
-                        .parse()
-                        .unwrap();
+                        .and_then(|index| resolve_index(index, v_data.len()))
+                        .ok_or_else(invalid)?;
+                    let vt = match split.next() {
+                        None => None,
+                        Some("") => {
+                            // `v//vn`, which only makes sense if a normal follows
+                            if split.clone().next().is_none() {
+                                return Err(invalid());
+                            }
+                            None
+                        }
+                        Some(index) => {
+                            Some(resolve_index(index, vt_data.len()).ok_or_else(invalid)?)
+                        }
+                    };
+                    let vn = match split.next() {
+                        None => None,
+                        Some(index) => {
+                            Some(resolve_index(index, vn_data.len()).ok_or_else(invalid)?)
+                        }
+                    };
+                    if split.next().is_some() {
+                        return Err(invalid());
+                    }
 
-                    i_data.push(vi - 1);
-                    ni_data.push(ni - 1);
-                    ti_data.push(ti - 1);
+                    face.push(FaceVertex { v, vt, vn });
+                }
+
+                // Fan triangulation, which is exact for the convex polygons
+                // that modelling tools export
+                let part = *part.get_or_insert_with(|| find_part(&mut parts, &object));
+                for i in 1..face.len() - 1 {
+                    triangles.push(Triangle {
+                        corners: [face[0], face[i], face[i + 1]],
+                        smoothing_group,
+                        part,
+                        material,
+                    });
                 }
             }
             "vn" => {
-                let x: f32 = split.next().unwrap().parse().unwrap();
-                let y: f32 = split.next().unwrap().parse().unwrap();
-                let z: f32 = split.next().unwrap().parse().unwrap();
-                vn_data.push((x, y, z));
+                let x: f32 = tokens.number()?;
+                let y: f32 = tokens.number()?;
+                let z: f32 = tokens.number()?;
+                vn_data.push([x, y, z]);
             }
             "vt" => {
-                let u: f32 = split.next().unwrap().parse().unwrap();
-                let v: f32 = split.next().unwrap().parse().unwrap();
-                vt_data.push((u, 1.0 - v));
+                let u: f32 = tokens.number()?;
+                let v: f32 = tokens.optional_number()?.unwrap_or(0.0);
+                vt_data.push([u, 1.0 - v]);
             }
-            "s" => {}
-            "mtllib" => {}
-            "usemtl" => {}
-            _ => {
-                panic!("Unrecognized .obj command.")
+            "s" => {
+                let (column, group) = tokens.argument()?;
+                smoothing_group = match group {
+                    "off" => 0,
+                    group => tokens.parse_number(column, group)?,
+                };
+            }
+            "mtllib" => {
+                // Several libraries may be listed on one line. A library that
+                // can't be loaded leaves its materials undefined, faces using
+                // them get the default material.
+                while let Some((column, file_name)) = tokens.optional_argument() {
+                    let mtl_path = dir.join(file_name);
+                    dependencies.push(mtl_path.clone());
+                    match material::load(&mtl_path) {
+                        Ok(library) => materials.extend(library),
+                        Err(err) => {
+                            let err = ObjError::MaterialLibrary {
+                                line: line_idx + 1,
+                                column,
+                                path: mtl_path,
+                                source: Box::new(err),
+                            };
+                            eprintln!("Warning: {}", err);
+                        }
+                    }
+                }
             }
+            "usemtl" => {
+                let (column, name) = tokens.argument()?;
+                let index = match materials.iter().position(|material| material.name == name) {
+                    Some(index) => index,
+                    None => {
+                        let err = ObjError::UnknownMaterial {
+                            line: line_idx + 1,
+                            column,
+                            token: name.to_string(),
+                        };
+                        eprintln!("Warning: {}, using the default material", err);
+                        // Later faces using the same name share the default
+                        materials.push(Material {
+                            name: name.to_string(),
+                            ..Default::default()
+                        });
+                        materials.len() - 1
+                    }
+                };
+                material = Some(index);
+            }
+            // Points, lines, like the loose edges Blender exports, and free-form
+            // geometry can't be drawn as triangles and are left out
+            "p" | "l" | "vp" | "cstype" | "deg" | "bmat" | "step" | "curv" | "curv2" | "surf"
+            | "parm" | "trim" | "hole" | "scrv" | "sp" | "end" | "con" => {}
+            _ => return Err(tokens.unknown_command()),
         }
-        current_line.clear();
     }
 
-    let mut vert_list: Vec<Vertex> = vec![];
-    for ((vertices, normals), texture_coords) in i_data
-        .chunks(3)
-        .zip(ni_data.chunks(3))
-        .zip(ti_data.chunks(3))
-    {
-        for i in 0..3 {
-            let vertex_idx = vertices[i] as usize;
-            let vertex = v_data[vertex_idx];
-
-            let normal_idx = normals[i] as usize;
-            let normal = vn_data[normal_idx];
+    // Group faces by part and material so each material of a part can be drawn
+    // with a single call
+    triangles.sort_by_key(|triangle| (triangle.part, triangle.material));
 
-            let texture_idx = texture_coords[i] as usize;
-            let texture = vt_data[texture_idx];
+    let mut vert_list: Vec<Vertex> = vec![];
+    for triangle in &triangles {
+        let submeshes = &mut parts[triangle.part].submeshes;
+        match submeshes.last_mut() {
+            Some(submesh) if submesh.material == triangle.material => submesh.range.end += 3,
+            _ => submeshes.push(SubMeshData {
+                material: triangle.material,
+                range: vert_list.len()..vert_list.len() + 3,
+            }),
+        }
 
+        for corner in &triangle.corners {
             vert_list.push(Vertex {
-                position: [vertex.0, vertex.1, vertex.2],
-                normal: [normal.0, normal.1, normal.2],
-                texture_coord: [texture.0, texture.1],
+                position: v_data[corner.v],
+                normal: corner.vn.map_or([0.0, 0.0, 0.0], |vn| vn_data[vn]),
+                texture_coord: corner.vt.map_or([0.0, 0.0], |vt| vt_data[vt]),
             });
         }
     }
-    (vert_list, i_data)
+
+    // Generate normals for the corners that didn't come with one
+    let corners = triangles.iter().flat_map(|triangle| &triangle.corners);
+    if corners.clone().any(|corner| corner.vn.is_none()) {
+        let smoothing_groups: Vec<u32> = triangles.iter().map(|t| t.smoothing_group).collect();
+        let mut generated = vert_list.clone();
+        if smoothing_groups.iter().all(|&group| group == 0) {
+            normals::flat(&mut generated);
+        } else {
+            normals::smooth(&mut generated, &smoothing_groups, crease_angle);
+        }
+
+        for ((vertex, generated), corner) in vert_list.iter_mut().zip(generated).zip(corners) {
+            if corner.vn.is_none() {
+                vertex.normal = generated.normal;
+            }
+        }
+    }
+
+    // Groups may be declared without ever getting any faces
+    parts.retain(|part| !part.submeshes.is_empty());
+
+    let (vertices, indices) = vertex::deduplicate(&vert_list);
+    Ok(MeshData {
+        bounds: Bounds::from_vertices(&vertices),
+        vertices,
+        indices,
+        materials,
+        parts,
+        dependencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, ops::Range};
+
+    use super::*;
+
+    fn parse_str(obj: &str) -> Result<MeshData, ObjError> {
+        parse(
+            Cursor::new(obj),
+            Path::new(""),
+            normals::DEFAULT_CREASE_ANGLE,
+        )
+    }
+
+    const QUAD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn positions_only() {
+        let data = parse_str(&format!("{}f 1 2 3\n", QUAD)).unwrap();
+        assert_eq!(data.indices, [0, 1, 2]);
+        assert_eq!(data.vertices[2].position, [1.0, 1.0, 0.0]);
+        // Flat normal generated from the winding
+        assert_eq!(data.vertices[0].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn texture_coordinates() {
+        let obj = format!("{}vt 0 0\nvt 1 0\nvt 1 0.25\nf 1/1 2/2 3/3\n", QUAD);
+        let data = parse_str(&obj).unwrap();
+        // V is flipped to OpenGL's texture origin
+        assert_eq!(data.vertices[2].texture_coord, [1.0, 0.75]);
+    }
+
+    #[test]
+    fn normals_without_texture_coordinates() {
+        let obj = format!("{}vn 0 0 -1\nf 1//1 2//1 3//1\n", QUAD);
+        let data = parse_str(&obj).unwrap();
+        assert!(data.vertices.iter().all(|v| v.normal == [0.0, 0.0, -1.0]));
+        assert!(data.vertices.iter().all(|v| v.texture_coord == [0.0, 0.0]));
+    }
+
+    #[test]
+    fn negative_indices() {
+        let relative = parse_str(&format!("{}f -4 -3 -2\n", QUAD)).unwrap();
+        let absolute = parse_str(&format!("{}f 1 2 3\n", QUAD)).unwrap();
+        assert_eq!(relative.vertices, absolute.vertices);
+        assert_eq!(relative.indices, absolute.indices);
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let data = parse_str(&format!("{}f 1 2 3 4\n", QUAD)).unwrap();
+        // Two triangles sharing the diagonal from the first corner
+        assert_eq!(data.vertices.len(), 4);
+        assert_eq!(data.indices, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn lines_and_points_are_skipped() {
+        let data = parse_str(&format!("{}l 1 2\np 3\nf 1 2 3\n", QUAD)).unwrap();
+        assert_eq!(data.indices.len(), 3);
+    }
+
+    #[test]
+    fn missing_materials_use_the_default() {
+        let obj = format!(
+            "mtllib missing.mtl\n{}usemtl Metal\nf 1 2 3\nusemtl Metal\nf 1 3 4\n",
+            QUAD
+        );
+        let data = parse_str(&obj).unwrap();
+        assert_eq!(data.materials.len(), 1);
+        assert_eq!(data.materials[0].name, "Metal");
+        assert_eq!(data.materials[0].diffuse, Material::default().diffuse);
+        assert_eq!(data.parts[0].submeshes.len(), 1);
+        assert_eq!(data.parts[0].submeshes[0].material, Some(0));
+    }
+
+    type Submeshes = Vec<(Option<usize>, Range<usize>)>;
+
+    // Name of every part with the material and index range of its submeshes
+    fn parts(data: &MeshData) -> Vec<(&str, Submeshes)> {
+        data.parts
+            .iter()
+            .map(|part| {
+                let submeshes = part
+                    .submeshes
+                    .iter()
+                    .map(|submesh| (submesh.material, submesh.range.clone()))
+                    .collect();
+                (part.name.as_str(), submeshes)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn faces_before_any_object_get_an_unnamed_part() {
+        let data = parse_str(&format!(
+            "{}f 1 2 3
+o Tank
+f 1 3 4
+",
+            QUAD
+        ))
+        .unwrap();
+        assert_eq!(
+            parts(&data),
+            [("", vec![(None, 0..3)]), ("Tank", vec![(None, 3..6)])]
+        );
+    }
+
+    #[test]
+    fn groups_split_objects() {
+        // The first of several group names names the part, a `g` without
+        // names returns to the object
+        let obj = format!(
+            "{}o Tank
+f 1 2 3
+g Turret Hatch
+f 1 3 4
+g
+f 2 3 4
+",
+            QUAD
+        );
+        let data = parse_str(&obj).unwrap();
+        assert_eq!(
+            parts(&data),
+            [("Tank", vec![(None, 0..6)]), ("Turret", vec![(None, 6..9)])]
+        );
+    }
+
+    #[test]
+    fn repeated_groups_share_a_part() {
+        let obj = format!(
+            "{}g Hull
+f 1 2 3
+g Turret
+f 1 3 4
+g Hull
+f 2 3 4
+",
+            QUAD
+        );
+        let data = parse_str(&obj).unwrap();
+        assert_eq!(
+            parts(&data),
+            [("Hull", vec![(None, 0..6)]), ("Turret", vec![(None, 6..9)])]
+        );
+    }
+
+    #[test]
+    fn triangles_are_grouped_by_part_and_material() {
+        let obj = format!(
+            "{}g Hull
+usemtl Red
+f 1 2 3
+usemtl Blue
+f 1 3 4
+\
+             g Turret
+usemtl Red
+f 2 3 4
+\
+             g Hull
+usemtl Red
+f 1 2 4
+",
+            QUAD
+        );
+        let data = parse_str(&obj).unwrap();
+        assert_eq!(data.materials[0].name, "Red");
+        assert_eq!(data.materials[1].name, "Blue");
+        assert_eq!(
+            parts(&data),
+            [
+                ("Hull", vec![(Some(0), 0..6), (Some(1), 6..9)]),
+                ("Turret", vec![(Some(0), 9..12)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        match parse_str("v 0 0 0\nv 0 x 0\n") {
+            Err(ObjError::InvalidNumber {
+                line: 2,
+                column: 5,
+                token,
+            }) => assert_eq!(token, "x"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        match parse_str(&format!("{}f 1 2 5\n", QUAD)) {
+            Err(ObjError::InvalidIndex {
+                line: 5,
+                column: 7,
+                token,
+            }) => assert_eq!(token, "5"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        match parse_str(&format!("{}  f 0 1 2\n", QUAD)) {
+            Err(ObjError::InvalidIndex {
+                line: 5, column: 5, ..
+            }) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        match parse_str("v 0 0\n") {
+            Err(ObjError::MissingArgument {
+                line: 1,
+                column: 6,
+                command,
+            }) => assert_eq!(command, "v"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        match parse_str("\n# comment\n bogus 1\n") {
+            Err(ObjError::UnknownCommand {
+                line: 3,
+                column: 2,
+                token,
+            }) => assert_eq!(token, "bogus"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
 }
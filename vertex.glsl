@@ -3,24 +3,30 @@ in vec3 position;
 in vec3 normal;
 in vec2 texture_coord;
 
+// Per-instance attributes
+in mat4 model;
+in vec3 tint;
+
 out vec3 frag_normal;
 out vec3 frag_pos;
 out vec2 tex_coord;
+out vec3 frag_tint;
+// Position as seen from the shadow casting light
+out vec4 frag_light_space_pos;
 
-uniform mat4 model;
 uniform mat4 view;
 uniform mat4 projection;
+uniform mat4 light_space;
 
 void main() {
-    mat4 matrix = projection * view * model;
-    
     vec4 world_pos = model * vec4(position, 1.0);
     vec4 screen_pos = projection * view * world_pos;
 
     // This is to transform the normals to world space from model space
     frag_normal = transpose(inverse(mat3(model))) * normal;
-    frag_pos = vec3(model * vec4(position, 1.0));
+    frag_pos = vec3(world_pos);
     gl_Position = screen_pos;
     tex_coord = texture_coord;
-
+    frag_tint = tint;
+    frag_light_space_pos = light_space * world_pos;
 }